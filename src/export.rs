@@ -0,0 +1,1290 @@
+// 描画内容の書き出し
+//
+// draw()で作られた曲線・ベクトル・多角形・文字を、ウィンドウを使わずに画像などへ変換する。
+// 画像 (SVG / PNG)、アニメーション (GIF / APNG / 連番PNG)、LaTeX (TikZ / PGFPlots)、点のデータ (CSV / JSON) に対応する。
+
+use crate::scene::{vector_segments, Color, Scene, SceneItem};
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use eframe::egui::FontDefinitions;
+use serde::Serialize;
+use std::fmt::Write as _;
+use tiny_skia::{FillRule, LineCap, LineJoin, Paint, PathBuilder, Pixmap, PremultipliedColorU8, Stroke};
+
+// 書き出す図の内容と表示設定
+#[derive(Clone)]
+pub struct Figure {
+    pub scene: Scene,
+    pub view: Option<[f64; 4]>, // 表示範囲 [x最小, y最小, x最大, y最大] (Noneならすべての図形が収まる範囲)
+    pub show_axes: bool,
+    pub show_grid: bool,
+    pub data_aspect: Option<f32>, // 縦横比 (Noneなら揃えない)
+}
+
+// 多角形の塗りつぶしの不透明度 (画面上の表示 (egui_plotの既定) に合わせて、枠線の色を薄くして塗る)
+const POLYGON_FILL_OPACITY: f64 = 0.05;
+
+// 目盛りの間隔の目安 (ピクセル)
+const GRID_SPACING_PX: f64 = 80.0;
+
+// グリッド・軸・文字の色と大きさ
+const GRID_COLOR: Color = Color::rgb(200, 200, 200);
+const GRID_WIDTH: f32 = 0.5;
+const AXIS_COLOR: Color = Color::rgb(100, 100, 100);
+const AXIS_WIDTH: f32 = 1.0;
+const LABEL_COLOR: Color = Color::rgb(80, 80, 80);
+const TICK_FONT_SIZE: f32 = 11.0;
+const AXIS_LABEL_FONT_SIZE: f32 = 13.0;
+const LABEL_MARGIN: f64 = 4.0; // 画像の端から目盛りの数値までの間隔
+
+// PNGで日本語などを描くフォント
+const JAPANESE_FONT: &[u8] = include_bytes!("fonts/MPLUS1Code-VariableFont_wght.ttf");
+
+// 線の太さや文字の大きさの基準になる解像度 (この解像度で画面上と同じ大きさになる)
+pub const BASE_DPI: f64 = 96.0;
+
+impl Figure {
+    // 実際に使う表示範囲を求める
+    // 範囲の指定がなければ図形全体に余白を付けた範囲にし、縦横比の指定があれば画像の大きさに合わせて広げる
+    pub fn resolve_view(&self, width: f64, height: f64) -> [f64; 4] {
+        let [mut min_x, mut min_y, mut max_x, mut max_y] = match self.view {
+            Some(view) => view,
+            None => with_margin(self.scene.bounds().unwrap_or([-1.0, -1.0, 1.0, 1.0])),
+        };
+        if let Some(aspect) = self.data_aspect {
+            // 1ピクセルあたりのx方向の量 = aspect × y方向の量 になるよう、足りない方向を中心から広げる
+            let aspect = aspect as f64;
+            let units_x = (max_x - min_x) / width;
+            let units_y = (max_y - min_y) / height;
+            if units_x < units_y * aspect {
+                let half = units_y * aspect * width / 2.0;
+                let center = (min_x + max_x) / 2.0;
+                min_x = center - half;
+                max_x = center + half;
+            } else {
+                let half = units_x / aspect * height / 2.0;
+                let center = (min_y + max_y) / 2.0;
+                min_y = center - half;
+                max_y = center + half;
+            }
+        }
+        [min_x, min_y, max_x, max_y]
+    }
+}
+
+// 範囲の周りに余白を付ける
+fn with_margin([min_x, min_y, max_x, max_y]: [f64; 4]) -> [f64; 4] {
+    let margin_x = ((max_x - min_x) * 0.05).max(1e-3);
+    let margin_y = ((max_y - min_y) * 0.05).max(1e-3);
+    [min_x - margin_x, min_y - margin_y, max_x + margin_x, max_y + margin_y]
+}
+
+// 図の座標とピクセル座標の変換
+struct Transform {
+    view: [f64; 4],
+    width: f64,
+    height: f64,
+}
+
+impl Transform {
+    fn x(&self, x: f64) -> f64 {
+        (x - self.view[0]) / (self.view[2] - self.view[0]) * self.width
+    }
+
+    fn y(&self, y: f64) -> f64 {
+        self.height - (y - self.view[1]) / (self.view[3] - self.view[1]) * self.height
+    }
+}
+
+// 範囲rangeに対して、見やすい目盛り間隔 (1, 2, 5 × 10^n) を求める
+fn nice_step(range: f64, pixels: f64) -> f64 {
+    let raw = range * GRID_SPACING_PX / pixels;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+// min〜maxの範囲にある目盛りの値
+// 表示範囲が極端な場合に目盛りが増えすぎないよう、上限を超えたら何も返さない
+fn ticks(min: f64, max: f64, step: f64) -> Vec<f64> {
+    const MAX_TICKS: i64 = 1000;
+    if !step.is_finite() || step <= 0.0 {
+        return Vec::new();
+    }
+    let first = (min / step).ceil() as i64;
+    let last = (max / step).floor() as i64;
+    if last - first > MAX_TICKS {
+        return Vec::new();
+    }
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+// 目盛りの値を間隔に応じた桁数で表示する
+fn format_tick(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let text = format!("{:.*}", decimals, value);
+    // -0.0 を 0 と表示する
+    if text.trim_start_matches('-').chars().all(|c| c == '0' || c == '.') {
+        text.trim_start_matches('-').to_string()
+    } else {
+        text
+    }
+}
+
+fn svg_color(color: Color) -> String {
+    format!("rgb({},{},{})", color.r(), color.g(), color.b())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// 座標変換と目盛りの配置 (書き出す形式によらず共通)
+struct Layout {
+    transform: Transform,
+    step_x: f64, // 目盛りの間隔
+    step_y: f64,
+    ticks_x: Vec<(f64, String)>, // (値, 表示する文字列)
+    ticks_y: Vec<(f64, String)>,
+}
+
+impl Layout {
+    fn new(figure: &Figure, width: u32, height: u32) -> Self {
+        let (w, h) = (width as f64, height as f64);
+        let view = figure.resolve_view(w, h);
+        let step_x = nice_step(view[2] - view[0], w);
+        let step_y = nice_step(view[3] - view[1], h);
+        let label = |step: f64| move |v: f64| (v, format_tick(v, step));
+        Self {
+            transform: Transform { view, width: w, height: h },
+            step_x,
+            step_y,
+            ticks_x: ticks(view[0], view[2], step_x).into_iter().map(label(step_x)).collect(),
+            ticks_y: ticks(view[1], view[3], step_y).into_iter().map(label(step_y)).collect(),
+        }
+    }
+
+    // 原点を通る軸の位置 (表示範囲外ならNone)
+    fn origin_x(&self) -> Option<f64> {
+        let view = self.transform.view;
+        (view[0] <= 0.0 && 0.0 <= view[2]).then(|| self.transform.x(0.0))
+    }
+
+    fn origin_y(&self) -> Option<f64> {
+        let view = self.transform.view;
+        (view[1] <= 0.0 && 0.0 <= view[3]).then(|| self.transform.y(0.0))
+    }
+}
+
+// 有限でない点で区切った、2点以上からなる点列
+fn finite_runs(points: &[[f64; 2]]) -> impl Iterator<Item = &[[f64; 2]]> {
+    points
+        .split(|p| !p[0].is_finite() || !p[1].is_finite())
+        .filter(|run| run.len() >= 2)
+}
+
+// 点列をSVGのpoints属性の形にする
+fn svg_points(run: &[[f64; 2]], transform: &Transform) -> String {
+    run.iter()
+        .map(|p| format!("{:.2},{:.2}", transform.x(p[0]), transform.y(p[1])))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// 図をSVGにする
+pub fn to_svg(figure: &Figure, width: u32, height: u32) -> String {
+    let layout = Layout::new(figure, width, height);
+    let transform = &layout.transform;
+    let (w, h) = (width as f64, height as f64);
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#);
+    let _ = writeln!(svg, r#"<defs><clipPath id="plot-area"><rect x="0" y="0" width="{width}" height="{height}"/></clipPath></defs>"#);
+    let _ = writeln!(svg, r#"<rect x="0" y="0" width="{width}" height="{height}" fill="white"/>"#);
+
+    // グリッド
+    if figure.show_grid {
+        let _ = writeln!(svg, r#"<g stroke="{}" stroke-width="{}">"#, svg_color(GRID_COLOR), GRID_WIDTH);
+        for (x, _) in &layout.ticks_x {
+            let px = transform.x(*x);
+            let _ = writeln!(svg, r#"<line x1="{px:.2}" y1="0" x2="{px:.2}" y2="{height}"/>"#);
+        }
+        for (y, _) in &layout.ticks_y {
+            let py = transform.y(*y);
+            let _ = writeln!(svg, r#"<line x1="0" y1="{py:.2}" x2="{width}" y2="{py:.2}"/>"#);
+        }
+        let _ = writeln!(svg, "</g>");
+    }
+
+    // 原点を通る軸
+    if figure.show_axes {
+        let _ = writeln!(svg, r#"<g stroke="{}" stroke-width="{}">"#, svg_color(AXIS_COLOR), AXIS_WIDTH);
+        if let Some(px) = layout.origin_x() {
+            let _ = writeln!(svg, r#"<line x1="{px:.2}" y1="0" x2="{px:.2}" y2="{height}"/>"#);
+        }
+        if let Some(py) = layout.origin_y() {
+            let _ = writeln!(svg, r#"<line x1="0" y1="{py:.2}" x2="{width}" y2="{py:.2}"/>"#);
+        }
+        let _ = writeln!(svg, "</g>");
+    }
+
+    let _ = writeln!(svg, r#"<g clip-path="url(#plot-area)" stroke-linejoin="round" stroke-linecap="round">"#);
+
+    // 図形 (追加された順)
+    for item in &figure.scene.items {
+        match item {
+            SceneItem::Polygon { name, points, style } => {
+                let Some(run) = finite_runs(points).next() else {
+                    continue;
+                };
+                let _ = writeln!(
+                    svg,
+                    r#"<polygon points="{}" fill="{}" fill-opacity="{}" stroke="{}" stroke-width="{}"><title>{}</title></polygon>"#,
+                    svg_points(run, transform), svg_color(style.stroke), POLYGON_FILL_OPACITY, svg_color(style.stroke), style.weight, escape_xml(name)
+                );
+            }
+            SceneItem::Curve { name, points, style, .. } => {
+                let _ = writeln!(svg, r#"<g fill="none" stroke="{}" stroke-width="{}"><title>{}</title>"#, svg_color(style.color), style.weight, escape_xml(name));
+                for run in finite_runs(points) {
+                    let _ = writeln!(svg, r#"<polyline points="{}"/>"#, svg_points(run, transform));
+                }
+                let _ = writeln!(svg, "</g>");
+            }
+            SceneItem::Vector { name, origin, tip, style, .. } => {
+                let _ = writeln!(svg, r#"<g fill="none" stroke="{}" stroke-width="{}"><title>{}</title>"#, svg_color(style.color), style.weight, escape_xml(name));
+                for [a, b] in vector_segments(*origin, *tip) {
+                    let _ = writeln!(
+                        svg,
+                        r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}"/>"#,
+                        transform.x(a[0]), transform.y(a[1]), transform.x(b[0]), transform.y(b[1])
+                    );
+                }
+                let _ = writeln!(svg, "</g>");
+            }
+            SceneItem::Text { text, position, style } => {
+                let _ = writeln!(
+                    svg,
+                    r#"<text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="{}" fill="{}" stroke="none" text-anchor="middle" dominant-baseline="central">{}</text>"#,
+                    transform.x(position[0]), transform.y(position[1]), style.size, svg_color(style.color), escape_xml(text)
+                );
+            }
+        }
+    }
+
+    let _ = writeln!(svg, "</g>");
+
+    // 目盛りの数値と軸ラベル
+    if figure.show_axes {
+        let _ = writeln!(svg, r#"<g font-family="sans-serif" font-size="{}" fill="{}">"#, TICK_FONT_SIZE, svg_color(LABEL_COLOR));
+        for (x, text) in &layout.ticks_x {
+            let _ = writeln!(svg, r#"<text x="{:.2}" y="{}" text-anchor="middle">{}</text>"#, transform.x(*x), h - LABEL_MARGIN, text);
+        }
+        for (y, text) in &layout.ticks_y {
+            let _ = writeln!(svg, r#"<text x="{}" y="{:.2}" dominant-baseline="middle">{}</text>"#, LABEL_MARGIN, transform.y(*y), text);
+        }
+        let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="end" font-size="{}">x</text>"#, w - 6.0, h - 18.0, AXIS_LABEL_FONT_SIZE);
+        let _ = writeln!(svg, r#"<text x="18" y="16" font-size="{}">y</text>"#, AXIS_LABEL_FONT_SIZE);
+        let _ = writeln!(svg, "</g>");
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// 文字列の揃え方
+#[derive(Clone, Copy)]
+enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+// PNGの描画先 (ピクセル単位の長さはscale倍する)
+struct Raster<'a> {
+    pixmap: Pixmap,
+    fonts: Vec<FontRef<'a>>, // 文字ごとに、その文字を含む最初のフォントで描く
+    scale: f32,
+}
+
+impl Raster<'_> {
+    fn paint(color: Color, alpha: f64) -> Paint<'static> {
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(color.r(), color.g(), color.b(), (alpha * 255.0).round() as u8);
+        paint.anti_alias = true;
+        paint
+    }
+
+    fn stroke(&mut self, path: Option<tiny_skia::Path>, color: Color, width: f32) {
+        let Some(path) = path else {
+            return;
+        };
+        let stroke = Stroke {
+            width: width * self.scale,
+            line_cap: LineCap::Round,
+            line_join: LineJoin::Round,
+            ..Stroke::default()
+        };
+        self.pixmap.stroke_path(&path, &Self::paint(color, 1.0), &stroke, tiny_skia::Transform::identity(), None);
+    }
+
+    fn fill(&mut self, path: Option<tiny_skia::Path>, color: Color, alpha: f64) {
+        if let Some(path) = path {
+            self.pixmap.fill_path(&path, &Self::paint(color, alpha), FillRule::EvenOdd, tiny_skia::Transform::identity(), None);
+        }
+    }
+
+    // 文字を含むフォント (どのフォントにもなければ最初のフォント)
+    fn font_for(&self, c: char) -> &FontRef<'_> {
+        self.fonts.iter().find(|font| font.glyph_id(c).0 != 0).unwrap_or(&self.fonts[0])
+    }
+
+    // 文字の縦の中心からベースラインまでの距離
+    fn middle(&self, size: f32) -> f32 {
+        let scaled = self.fonts[0].as_scaled(PxScale::from(size * self.scale));
+        (scaled.ascent() + scaled.descent()) / 2.0
+    }
+
+    // 文字列を描く。yはベースラインの位置
+    fn text(&mut self, text: &str, x: f32, y: f32, size: f32, align: TextAlign, color: Color) {
+        let scale = PxScale::from(size * self.scale);
+        let width: f32 = text.chars().map(|c| {
+            let scaled = self.font_for(c).as_scaled(scale);
+            scaled.h_advance(scaled.glyph_id(c))
+        }).sum();
+        let mut pen = match align {
+            TextAlign::Left => x,
+            TextAlign::Center => x - width / 2.0,
+            TextAlign::Right => x - width,
+        };
+        let (pixmap_width, pixmap_height) = (self.pixmap.width(), self.pixmap.height());
+        for c in text.chars() {
+            let font = self.font_for(c).clone();
+            let scaled = font.as_scaled(scale);
+            let id = scaled.glyph_id(c);
+            let glyph = id.with_scale_and_position(scale, ab_glyph::point(pen, y));
+            pen += scaled.h_advance(id);
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let pixels = self.pixmap.pixels_mut();
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i32 + gx as i32;
+                let py = bounds.min.y as i32 + gy as i32;
+                if px < 0 || py < 0 || px >= pixmap_width as i32 || py >= pixmap_height as i32 {
+                    return;
+                }
+                // 背景は不透明なので、文字色と背景色を被覆率で混ぜる
+                let pixel = &mut pixels[py as usize * pixmap_width as usize + px as usize];
+                let alpha = coverage.clamp(0.0, 1.0);
+                let mix = |src: u8, dst: u8| (src as f32 * alpha + dst as f32 * (1.0 - alpha)).round() as u8;
+                if let Some(mixed) = PremultipliedColorU8::from_rgba(
+                    mix(color.r(), pixel.red()),
+                    mix(color.g(), pixel.green()),
+                    mix(color.b(), pixel.blue()),
+                    mix(255, pixel.alpha()),
+                ) {
+                    *pixel = mixed;
+                }
+            });
+        }
+    }
+}
+
+// 点列をtiny-skiaのパスにする
+fn raster_path(run: &[[f64; 2]], transform: &Transform, close: bool) -> Option<tiny_skia::Path> {
+    let mut builder = PathBuilder::new();
+    for (i, p) in run.iter().enumerate() {
+        let (x, y) = (transform.x(p[0]) as f32, transform.y(p[1]) as f32);
+        if i == 0 {
+            builder.move_to(x, y);
+        } else {
+            builder.line_to(x, y);
+        }
+    }
+    if close {
+        builder.close();
+    }
+    builder.finish()
+}
+
+// 図をPNGにする (GPUを使わずCPUで描画する)
+// dpiは1インチあたりのピクセル数で、線の太さと文字の大きさを BASE_DPI に対する比で拡大し、PNGにも記録する
+pub fn to_png(figure: &Figure, width: u32, height: u32, dpi: f64) -> Result<Vec<u8>, String> {
+    let rgba = rasterize(figure, width, height, dpi)?;
+    let mut png_data = Vec::new();
+    let mut writer = png_encoder(&mut png_data, width, height, dpi).write_header().map_err(|e| format!("PNGを書き出せません: {}", e))?;
+    writer.write_image_data(&rgba).map_err(|e| format!("PNGを書き出せません: {}", e))?;
+    writer.finish().map_err(|e| format!("PNGを書き出せません: {}", e))?;
+    Ok(png_data)
+}
+
+// 解像度 (1メートルあたりのピクセル数) を記録するPNGの符号化器
+fn png_encoder<W: std::io::Write>(w: W, width: u32, height: u32, dpi: f64) -> png::Encoder<'static, W> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels_per_meter = (dpi / 0.0254).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions { xppu: pixels_per_meter, yppu: pixels_per_meter, unit: png::Unit::Meter }));
+    encoder
+}
+
+// 図をCPUで描画し、RGBA (乗算済みでない) の画素を返す
+fn rasterize(figure: &Figure, width: u32, height: u32, dpi: f64) -> Result<Vec<u8>, String> {
+    let layout = Layout::new(figure, width, height);
+    let transform = &layout.transform;
+    let (w, h) = (width as f32, height as f32);
+
+    // 目盛りの数値はeguiの既定のフォントで描き、それに含まれない文字 (日本語など) はエディタと同じM PLUS 1 Codeで描く
+    let fonts = FontDefinitions::default();
+    let font_data = fonts.font_data.get("Ubuntu-Light").ok_or("フォントが見つかりません")?;
+    let font = FontRef::try_from_slice(&font_data.font).map_err(|e| format!("フォントを読み込めません: {}", e))?;
+    let japanese_font = FontRef::try_from_slice(JAPANESE_FONT).map_err(|e| format!("フォントを読み込めません: {}", e))?;
+    let pixmap = Pixmap::new(width, height).ok_or_else(|| format!("画像の大きさが正しくありません: {}x{}", width, height))?;
+    let mut raster = Raster { pixmap, fonts: vec![font, japanese_font], scale: (dpi / BASE_DPI) as f32 };
+    raster.pixmap.fill(tiny_skia::Color::WHITE);
+
+    // グリッド
+    if figure.show_grid {
+        let mut builder = PathBuilder::new();
+        for (x, _) in &layout.ticks_x {
+            let px = transform.x(*x) as f32;
+            builder.move_to(px, 0.0);
+            builder.line_to(px, h);
+        }
+        for (y, _) in &layout.ticks_y {
+            let py = transform.y(*y) as f32;
+            builder.move_to(0.0, py);
+            builder.line_to(w, py);
+        }
+        raster.stroke(builder.finish(), GRID_COLOR, GRID_WIDTH);
+    }
+
+    // 原点を通る軸
+    if figure.show_axes {
+        let mut builder = PathBuilder::new();
+        if let Some(px) = layout.origin_x() {
+            builder.move_to(px as f32, 0.0);
+            builder.line_to(px as f32, h);
+        }
+        if let Some(py) = layout.origin_y() {
+            builder.move_to(0.0, py as f32);
+            builder.line_to(w, py as f32);
+        }
+        raster.stroke(builder.finish(), AXIS_COLOR, AXIS_WIDTH);
+    }
+
+    // 図形 (追加された順)
+    for item in &figure.scene.items {
+        match item {
+            SceneItem::Polygon { points, style, .. } => {
+                let Some(run) = finite_runs(points).next() else {
+                    continue;
+                };
+                raster.fill(raster_path(run, transform, true), style.stroke, POLYGON_FILL_OPACITY);
+                raster.stroke(raster_path(run, transform, true), style.stroke, style.weight);
+            }
+            SceneItem::Curve { points, style, .. } => {
+                for run in finite_runs(points) {
+                    raster.stroke(raster_path(run, transform, false), style.color, style.weight);
+                }
+            }
+            SceneItem::Vector { origin, tip, style, .. } => {
+                for segment in vector_segments(*origin, *tip) {
+                    raster.stroke(raster_path(&segment, transform, false), style.color, style.weight);
+                }
+            }
+            SceneItem::Text { text, position, style } => {
+                let y = transform.y(position[1]) as f32 + raster.middle(style.size);
+                raster.text(text, transform.x(position[0]) as f32, y, style.size, TextAlign::Center, style.color);
+            }
+        }
+    }
+
+    // 目盛りの数値と軸ラベル
+    if figure.show_axes {
+        let s = raster.scale;
+        let middle = raster.middle(TICK_FONT_SIZE);
+        for (x, text) in &layout.ticks_x {
+            raster.text(text, transform.x(*x) as f32, h - LABEL_MARGIN as f32 * s, TICK_FONT_SIZE, TextAlign::Center, LABEL_COLOR);
+        }
+        for (y, text) in &layout.ticks_y {
+            raster.text(text, LABEL_MARGIN as f32 * s, transform.y(*y) as f32 + middle, TICK_FONT_SIZE, TextAlign::Left, LABEL_COLOR);
+        }
+        raster.text("x", w - 6.0 * s, h - 18.0 * s, AXIS_LABEL_FONT_SIZE, TextAlign::Right, LABEL_COLOR);
+        raster.text("y", 18.0 * s, 16.0 * s, AXIS_LABEL_FONT_SIZE, TextAlign::Left, LABEL_COLOR);
+    }
+
+    Ok(raster.pixmap.pixels().iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect())
+}
+
+// アニメーションの書き出し形式
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    PngSequence, // 連番のPNGファイル (name_0000.png, name_0001.png, ...)
+}
+
+impl AnimationFormat {
+    pub fn label(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "GIF",
+            AnimationFormat::Apng => "APNG",
+            AnimationFormat::PngSequence => "連番PNG",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng | AnimationFormat::PngSequence => "png",
+        }
+    }
+}
+
+// アニメーションの各コマの図を書き出す
+// 表示範囲の指定がない場合は、コマごとに範囲が変わって揺れないよう、すべてのコマの図形が収まる範囲に揃える
+pub fn write_animation(frames: &[Figure], width: u32, height: u32, dpi: f64, fps: f64, format: AnimationFormat, path: &std::path::Path) -> Result<(), String> {
+    if frames.is_empty() {
+        return Err("書き出すコマがありません".to_string());
+    }
+    if !(fps > 0.0 && fps.is_finite()) {
+        return Err("フレームレートは正の数にしてください".to_string());
+    }
+    let common_view = frames.iter()
+        .filter_map(|figure| figure.scene.bounds())
+        .reduce(|a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])])
+        .map(with_margin);
+    let render = |figure: &Figure| {
+        let view = figure.view.or(common_view);
+        let figure = Figure { view, ..figure.clone() };
+        rasterize(&figure, width, height, dpi)
+    };
+    let io_error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+
+    match format {
+        AnimationFormat::Gif => {
+            let (Ok(w), Ok(h)) = (u16::try_from(width), u16::try_from(height)) else {
+                return Err(format!("GIFの大きさは {}x{} までです", u16::MAX, u16::MAX));
+            };
+            let file = std::fs::File::create(path).map_err(io_error)?;
+            let mut encoder = gif::Encoder::new(std::io::BufWriter::new(file), w, h, &[]).map_err(|e| format!("GIFを書き出せません: {}", e))?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("GIFを書き出せません: {}", e))?;
+            // GIFの表示時間は1/100秒単位
+            let delay = (100.0 / fps).round().clamp(1.0, u16::MAX as f64) as u16;
+            for figure in frames {
+                let mut rgba = render(figure)?;
+                let mut frame = gif::Frame::from_rgba_speed(w, h, &mut rgba, 10);
+                frame.delay = delay;
+                encoder.write_frame(&frame).map_err(|e| format!("GIFを書き出せません: {}", e))?;
+            }
+            let mut writer = encoder.into_inner().map_err(io_error)?;
+            std::io::Write::flush(&mut writer).map_err(io_error)
+        }
+        AnimationFormat::Apng => {
+            let file = std::fs::File::create(path).map_err(io_error)?;
+            let png_error = |e: png::EncodingError| format!("APNGを書き出せません: {}", e);
+            let mut encoder = png_encoder(std::io::BufWriter::new(file), width, height, dpi);
+            encoder.set_animated(frames.len() as u32, 0).map_err(png_error)?;
+            // 表示時間は1/1000秒単位で指定する
+            let delay = (1000.0 / fps).round().clamp(1.0, u16::MAX as f64) as u16;
+            encoder.set_frame_delay(delay, 1000).map_err(png_error)?;
+            let mut writer = encoder.write_header().map_err(png_error)?;
+            for figure in frames {
+                writer.write_image_data(&render(figure)?).map_err(png_error)?;
+            }
+            writer.finish().map_err(png_error)
+        }
+        AnimationFormat::PngSequence => {
+            let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "frame".to_string());
+            let digits = frames.len().saturating_sub(1).to_string().len().max(4);
+            for (i, figure) in frames.iter().enumerate() {
+                let frame_path = path.with_file_name(format!("{}_{:0digits$}.png", stem, i));
+                let mut png_data = Vec::new();
+                let mut writer = png_encoder(&mut png_data, width, height, dpi).write_header().map_err(|e| format!("PNGを書き出せません: {}", e))?;
+                writer.write_image_data(&render(figure)?).map_err(|e| format!("PNGを書き出せません: {}", e))?;
+                writer.finish().map_err(|e| format!("PNGを書き出せません: {}", e))?;
+                std::fs::write(&frame_path, png_data).map_err(|e| format!("{}: {}", frame_path.display(), e))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// LaTeXで書き出す形式
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TexFlavor {
+    Tikz,     // TikZの図形として描く (画面上の表示をそのまま再現する)
+    Pgfplots, // PGFPlotsのaxis環境に曲線などを追加する (目盛りや軸はPGFPlotsが描く)
+}
+
+// 1ピクセル (BASE_DPIでの1px) をTeXの長さにする
+const CM_PER_PX: f64 = 2.54 / BASE_DPI;
+const BP_PER_PX: f64 = 72.0 / BASE_DPI;
+
+// TeXで使う色を登録順に名前を付けて管理する
+#[derive(Default)]
+struct TexColors(Vec<Color>);
+
+impl TexColors {
+    fn name(&mut self, color: Color) -> String {
+        let index = match self.0.iter().position(|c| *c == color) {
+            Some(index) => index,
+            None => {
+                self.0.push(color);
+                self.0.len() - 1
+            }
+        };
+        format!("graphcolor{}", index)
+    }
+
+    fn definitions(&self) -> String {
+        let mut tex = String::new();
+        for (i, color) in self.0.iter().enumerate() {
+            let _ = writeln!(tex, r"\definecolor{{graphcolor{}}}{{RGB}}{{{},{},{}}}", i, color.r(), color.g(), color.b());
+        }
+        tex
+    }
+}
+
+// 名前をTeXのコメントにする (改行で途切れないようにする)
+fn tex_comment(name: &str) -> String {
+    format!("% {}", name.replace(['\n', '\r'], " "))
+}
+
+// 文字列をTeXの特殊文字が効かないようにする
+fn escape_tex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str(r"\textbackslash{}"),
+            '~' => escaped.push_str(r"\textasciitilde{}"),
+            '^' => escaped.push_str(r"\textasciicircum{}"),
+            '{' | '}' | '$' | '&' | '%' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// 文字の大きさ (ピクセル) をTeXのフォント指定にする
+fn tex_font_size(size: f32) -> String {
+    let size = size as f64 * BP_PER_PX;
+    format!(r"\fontsize{{{:.2}bp}}{{{:.2}bp}}\selectfont", size, size * 1.2)
+}
+
+// 座標をいくつかずつ改行しながら並べる
+fn tex_coordinates(points: impl Iterator<Item = String>, separator: &str) -> String {
+    const PER_LINE: usize = 6;
+    let points: Vec<String> = points.collect();
+    points
+        .chunks(PER_LINE)
+        .map(|chunk| chunk.join(separator))
+        .collect::<Vec<_>>()
+        .join(&format!("{}\n    ", separator.trim_end()))
+}
+
+// 図をLaTeXのコードにする
+// standaloneならpdflatexでそのままコンパイルできる文書にし、そうでなければ文書に貼り付ける部分だけを返す
+// 大きさは BASE_DPI でのピクセル数をそのまま長さにしたもので、線の太さも画面上と同じになる
+pub fn to_tex(figure: &Figure, width: u32, height: u32, flavor: TexFlavor, standalone: bool) -> String {
+    let layout = Layout::new(figure, width, height);
+    let mut colors = TexColors::default();
+    let body = match flavor {
+        TexFlavor::Tikz => tikz_body(figure, &layout, &mut colors),
+        TexFlavor::Pgfplots => pgfplots_body(figure, &layout, &mut colors),
+    };
+
+    let mut tex = String::new();
+    if standalone {
+        match flavor {
+            TexFlavor::Tikz => tex.push_str("\\documentclass[tikz]{standalone}\n"),
+            TexFlavor::Pgfplots => tex.push_str("\\documentclass{standalone}\n\\usepackage{pgfplots}\n\\pgfplotsset{compat=1.16}\n"),
+        }
+        tex.push_str("\\begin{document}\n");
+    }
+    tex.push_str("\\begin{tikzpicture}\n");
+    tex.push_str(&colors.definitions());
+    tex.push_str(&body);
+    tex.push_str("\\end{tikzpicture}\n");
+    if standalone {
+        tex.push_str("\\end{document}\n");
+    }
+    tex
+}
+
+// TikZの図形として描く。座標は画像の左下を原点としたcm単位
+fn tikz_body(figure: &Figure, layout: &Layout, colors: &mut TexColors) -> String {
+    let transform = &layout.transform;
+    let (w, h) = (transform.width * CM_PER_PX, transform.height * CM_PER_PX);
+    let point = |p: &[f64; 2]| format!("({:.4},{:.4})", transform.x(p[0]) * CM_PER_PX, (transform.height - transform.y(p[1])) * CM_PER_PX);
+
+    let mut tex = String::new();
+    let _ = writeln!(tex, r"\fill[white] (0,0) rectangle ({w:.4},{h:.4});");
+
+    // グリッド
+    if figure.show_grid {
+        let grid = colors.name(GRID_COLOR);
+        for (x, _) in &layout.ticks_x {
+            let px = transform.x(*x) * CM_PER_PX;
+            let _ = writeln!(tex, r"\draw[{grid}, line width={:.3}bp] ({px:.4},0) -- ({px:.4},{h:.4});", GRID_WIDTH as f64 * BP_PER_PX);
+        }
+        for (y, _) in &layout.ticks_y {
+            let py = (transform.height - transform.y(*y)) * CM_PER_PX;
+            let _ = writeln!(tex, r"\draw[{grid}, line width={:.3}bp] (0,{py:.4}) -- ({w:.4},{py:.4});", GRID_WIDTH as f64 * BP_PER_PX);
+        }
+    }
+
+    // 原点を通る軸
+    if figure.show_axes {
+        let axis = colors.name(AXIS_COLOR);
+        if let Some(px) = layout.origin_x() {
+            let px = px * CM_PER_PX;
+            let _ = writeln!(tex, r"\draw[{axis}, line width={:.3}bp] ({px:.4},0) -- ({px:.4},{h:.4});", AXIS_WIDTH as f64 * BP_PER_PX);
+        }
+        if let Some(py) = layout.origin_y() {
+            let py = (transform.height - py) * CM_PER_PX;
+            let _ = writeln!(tex, r"\draw[{axis}, line width={:.3}bp] (0,{py:.4}) -- ({w:.4},{py:.4});", AXIS_WIDTH as f64 * BP_PER_PX);
+        }
+    }
+
+    tex.push_str("\\begin{scope}[line cap=round, line join=round]\n");
+    let _ = writeln!(tex, r"\clip (0,0) rectangle ({w:.4},{h:.4});");
+
+    // 図形 (追加された順)
+    for item in &figure.scene.items {
+        let _ = writeln!(tex, "{}", tex_comment(item.name()));
+        match item {
+            SceneItem::Polygon { points, style, .. } => {
+                let Some(run) = finite_runs(points).next() else {
+                    continue;
+                };
+                let color = colors.name(style.stroke);
+                let _ = writeln!(
+                    tex,
+                    "\\filldraw[draw={color}, fill={color}, fill opacity={}, line width={:.3}bp]\n    {} -- cycle;",
+                    POLYGON_FILL_OPACITY, style.weight as f64 * BP_PER_PX, tex_coordinates(run.iter().map(point), " -- ")
+                );
+            }
+            SceneItem::Curve { points, style, .. } => {
+                let color = colors.name(style.color);
+                for run in finite_runs(points) {
+                    let _ = writeln!(
+                        tex,
+                        "\\draw[{color}, line width={:.3}bp]\n    {};",
+                        style.weight as f64 * BP_PER_PX, tex_coordinates(run.iter().map(point), " -- ")
+                    );
+                }
+            }
+            // 画面上と同じ形の矢じりを線で描く
+            SceneItem::Vector { origin, tip, style, .. } => {
+                let color = colors.name(style.color);
+                for [a, b] in vector_segments(*origin, *tip) {
+                    let _ = writeln!(tex, r"\draw[{color}, line width={:.3}bp] {} -- {};", style.weight as f64 * BP_PER_PX, point(&a), point(&b));
+                }
+            }
+            SceneItem::Text { text, position, style } => {
+                let color = colors.name(style.color);
+                let _ = writeln!(tex, r"\node[{color}, anchor=center, inner sep=0pt, font={}] at {} {{{}}};", tex_font_size(style.size), point(position), escape_tex(text));
+            }
+        }
+    }
+
+    tex.push_str("\\end{scope}\n");
+
+    // 目盛りの数値と軸ラベル
+    if figure.show_axes {
+        let label = colors.name(LABEL_COLOR);
+        let margin = LABEL_MARGIN * CM_PER_PX;
+        for (x, text) in &layout.ticks_x {
+            let _ = writeln!(tex, r"\node[{label}, anchor=base, font=\scriptsize] at ({:.4},{margin:.4}) {{${text}$}};", transform.x(*x) * CM_PER_PX);
+        }
+        for (y, text) in &layout.ticks_y {
+            let _ = writeln!(tex, r"\node[{label}, anchor=west, inner sep=0pt, font=\scriptsize] at ({margin:.4},{:.4}) {{${text}$}};", (transform.height - transform.y(*y)) * CM_PER_PX);
+        }
+        let _ = writeln!(tex, r"\node[{label}, anchor=base east, inner sep=0pt] at ({:.4},{:.4}) {{$x$}};", w - 6.0 * CM_PER_PX, 18.0 * CM_PER_PX);
+        let _ = writeln!(tex, r"\node[{label}, anchor=base west, inner sep=0pt] at ({:.4},{:.4}) {{$y$}};", 18.0 * CM_PER_PX, h - 16.0 * CM_PER_PX);
+    }
+    tex
+}
+
+// PGFPlotsのaxis環境として描く。座標は図の座標のまま
+fn pgfplots_body(figure: &Figure, layout: &Layout, colors: &mut TexColors) -> String {
+    let transform = &layout.transform;
+    let [min_x, min_y, max_x, max_y] = transform.view;
+    let point = |p: &[f64; 2]| format!("({},{})", p[0], p[1]);
+
+    let mut options = vec![
+        format!("width={:.4}cm", transform.width * CM_PER_PX),
+        format!("height={:.4}cm", transform.height * CM_PER_PX),
+        "scale only axis".to_string(),
+        format!("xmin={min_x}, xmax={max_x}"),
+        format!("ymin={min_y}, ymax={max_y}"),
+        "clip=true".to_string(),
+        "line cap=round, line join=round".to_string(),
+    ];
+    // 画面上と同じ間隔で目盛りを付ける
+    if layout.step_x.is_finite() && layout.step_y.is_finite() {
+        options.push(format!("xtick distance={}, ytick distance={}", layout.step_x, layout.step_y));
+    }
+    if figure.show_axes {
+        options.push("axis lines=middle, xlabel={$x$}, ylabel={$y$}".to_string());
+        options.push("tick label style={font=\\scriptsize}".to_string());
+    } else {
+        options.push("hide axis".to_string());
+    }
+    if figure.show_grid {
+        let grid = colors.name(GRID_COLOR);
+        options.push(format!("grid=major, major grid style={{{grid}, line width={:.3}bp}}", GRID_WIDTH as f64 * BP_PER_PX));
+    }
+
+    let mut tex = String::new();
+    let _ = writeln!(tex, "\\begin{{axis}}[\n    {}\n]", options.join(",\n    "));
+
+    // 図形 (追加された順)
+    for item in &figure.scene.items {
+        let _ = writeln!(tex, "{}", tex_comment(item.name()));
+        match item {
+            SceneItem::Polygon { points, style, .. } => {
+                let Some(run) = finite_runs(points).next() else {
+                    continue;
+                };
+                let color = colors.name(style.stroke);
+                let _ = writeln!(
+                    tex,
+                    "\\addplot[draw={color}, fill={color}, fill opacity={}, line width={:.3}bp, mark=none] coordinates {{\n    {}\n}} -- cycle;",
+                    POLYGON_FILL_OPACITY, style.weight as f64 * BP_PER_PX, tex_coordinates(run.iter().map(point), " ")
+                );
+            }
+            SceneItem::Curve { points, style, .. } => {
+                let color = colors.name(style.color);
+                for run in finite_runs(points) {
+                    let _ = writeln!(
+                        tex,
+                        "\\addplot[{color}, line width={:.3}bp, mark=none] coordinates {{\n    {}\n}};",
+                        style.weight as f64 * BP_PER_PX, tex_coordinates(run.iter().map(point), " ")
+                    );
+                }
+            }
+            // 画面上と同じ形の矢じりを線で描く
+            SceneItem::Vector { origin, tip, style, .. } => {
+                let color = colors.name(style.color);
+                for [a, b] in vector_segments(*origin, *tip) {
+                    let _ = writeln!(tex, r"\addplot[{color}, line width={:.3}bp, mark=none] coordinates {{{} {}}};", style.weight as f64 * BP_PER_PX, point(&a), point(&b));
+                }
+            }
+            SceneItem::Text { text, position, style } => {
+                let color = colors.name(style.color);
+                let _ = writeln!(tex, r"\node[{color}, anchor=center, inner sep=0pt, font={}] at (axis cs:{},{}) {{{}}};", tex_font_size(style.size), position[0], position[1], escape_tex(text));
+            }
+        }
+    }
+
+    tex.push_str("\\end{axis}\n");
+    tex
+}
+
+// 色を #rrggbb の形にする
+fn hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+// CSVの1項目 (区切り文字や引用符を含む場合は引用符で囲む)
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// 描画内容の点をCSVにする (1行に1点)
+// kindは line / vector / polygon、indexは図形の中での点の番号 (ベクトルは0が始点、1が終点)
+// tは曲線の各点とベクトルで関数に渡した値で、多角形では空欄にする
+pub fn to_csv(figure: &Figure) -> String {
+    let mut csv = String::from("kind,name,index,t,x,y,color,weight\n");
+    let mut row = |kind: &str, name: &str, index: usize, t: Option<f64>, p: [f64; 2], color: Color, weight: f32| {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            kind,
+            csv_field(name),
+            index,
+            t.map(|t| t.to_string()).unwrap_or_default(),
+            p[0],
+            p[1],
+            hex_color(color),
+            weight
+        );
+    };
+    for item in &figure.scene.items {
+        match item {
+            SceneItem::Curve { name, points, params, style } => {
+                for (i, p) in points.iter().enumerate() {
+                    row("line", name, i, params.get(i).copied(), *p, style.color, style.weight);
+                }
+            }
+            SceneItem::Vector { name, origin, tip, t, style } => {
+                for (i, p) in [origin, tip].into_iter().enumerate() {
+                    row("vector", name, i, Some(*t), *p, style.color, style.weight);
+                }
+            }
+            SceneItem::Polygon { name, points, style } => {
+                for (i, p) in points.iter().enumerate() {
+                    row("polygon", name, i, None, *p, style.stroke, style.weight);
+                }
+            }
+            // 文字は点のデータではないので書き出さない
+            SceneItem::Text { .. } => {}
+        }
+    }
+    csv
+}
+
+// JSONで書き出す曲線の点
+#[derive(Serialize)]
+struct DataPoint {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    t: Option<f64>,
+    x: f64,
+    y: f64,
+}
+
+#[derive(Serialize)]
+struct DataLine<'a> {
+    name: &'a str,
+    color: String,
+    weight: f32,
+    points: Vec<DataPoint>,
+}
+
+#[derive(Serialize)]
+struct DataVector<'a> {
+    name: &'a str,
+    color: String,
+    weight: f32,
+    t: f64,
+    origin: [f64; 2],
+    tip: [f64; 2],
+}
+
+#[derive(Serialize)]
+struct DataPolygon<'a> {
+    name: &'a str,
+    color: String,
+    weight: f32,
+    points: &'a [[f64; 2]],
+}
+
+#[derive(Serialize)]
+struct DataExport<'a> {
+    lines: Vec<DataLine<'a>>,
+    vectors: Vec<DataVector<'a>>,
+    polygons: Vec<DataPolygon<'a>>,
+}
+
+// 描画内容の点をJSONにする (有限でない座標はnullになる)
+pub fn to_data_json(figure: &Figure) -> String {
+    let mut data = DataExport { lines: Vec::new(), vectors: Vec::new(), polygons: Vec::new() };
+    for item in &figure.scene.items {
+        match item {
+            SceneItem::Curve { name, points, params, style } => data.lines.push(DataLine {
+                name,
+                color: hex_color(style.color),
+                weight: style.weight,
+                points: points.iter().enumerate()
+                    .map(|(i, p)| DataPoint { t: params.get(i).copied(), x: p[0], y: p[1] })
+                    .collect(),
+            }),
+            SceneItem::Vector { name, origin, tip, t, style } => data.vectors.push(DataVector {
+                name,
+                color: hex_color(style.color),
+                weight: style.weight,
+                t: *t,
+                origin: *origin,
+                tip: *tip,
+            }),
+            SceneItem::Polygon { name, points, style } => data.polygons.push(DataPolygon {
+                name,
+                color: hex_color(style.stroke),
+                weight: style.weight,
+                points,
+            }),
+            SceneItem::Text { .. } => {}
+        }
+    }
+    serde_json::to_string_pretty(&data).expect("描画内容はJSONに変換できる")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{LineStyle, PolygonStyle, TextStyle};
+
+    // 書き出しの確認に使う小さな図
+    fn figure() -> Figure {
+        let mut scene = Scene::default();
+        scene.push(SceneItem::Curve {
+            name: "curve".to_string(),
+            points: vec![[-1.0, -1.0], [0.0, 0.5], [f64::NAN, 0.0], [1.0, 1.0]],
+            params: vec![0.0, 0.5, 0.75, 1.0],
+            style: LineStyle { color: Color::rgb(255, 0, 0), weight: 2.0 },
+        });
+        scene.push(SceneItem::Vector {
+            name: "vector".to_string(),
+            origin: [0.0, 0.0],
+            tip: [0.5, 0.0],
+            t: 0.25,
+            style: LineStyle { color: Color::rgb(0, 0, 255), weight: 1.0 },
+        });
+        scene.push(SceneItem::Polygon {
+            name: "a<b".to_string(),
+            points: vec![[-0.5, -0.5], [0.5, -0.5], [0.0, 0.5]],
+            style: PolygonStyle { stroke: Color::rgb(0, 128, 0), weight: 1.5 },
+        });
+        scene.push(SceneItem::Text {
+            text: "x_1 & y".to_string(),
+            position: [0.0, -0.8],
+            style: TextStyle { color: Color::rgb(0, 0, 0), size: 12.0 },
+        });
+        Figure { scene, view: Some([-1.0, -1.0, 1.0, 1.0]), show_axes: false, show_grid: false, data_aspect: None }
+    }
+
+    #[test]
+    fn svg_snapshot() {
+        let expected = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100" viewBox="0 0 100 100">
+<defs><clipPath id="plot-area"><rect x="0" y="0" width="100" height="100"/></clipPath></defs>
+<rect x="0" y="0" width="100" height="100" fill="white"/>
+<g clip-path="url(#plot-area)" stroke-linejoin="round" stroke-linecap="round">
+<g fill="none" stroke="rgb(255,0,0)" stroke-width="2"><title>curve</title>
+<polyline points="0.00,100.00 50.00,25.00"/>
+</g>
+<g fill="none" stroke="rgb(0,0,255)" stroke-width="1"><title>vector</title>
+<line x1="50.00" y1="50.00" x2="75.00" y2="50.00"/>
+<line x1="75.00" y1="50.00" x2="68.24" y2="53.25"/>
+<line x1="75.00" y1="50.00" x2="68.24" y2="46.75"/>
+</g>
+<polygon points="25.00,75.00 75.00,75.00 50.00,25.00" fill="rgb(0,128,0)" fill-opacity="0.05" stroke="rgb(0,128,0)" stroke-width="1.5"><title>a&lt;b</title></polygon>
+<text x="50.00" y="90.00" font-family="sans-serif" font-size="12" fill="rgb(0,0,0)" stroke="none" text-anchor="middle" dominant-baseline="central">x_1 &amp; y</text>
+</g>
+</svg>
+"#;
+        assert_eq!(to_svg(&figure(), 100, 100), expected);
+    }
+
+    #[test]
+    fn svg_draws_axes_and_tick_labels() {
+        let svg = to_svg(&Figure { show_axes: true, show_grid: true, ..figure() }, 100, 100);
+        assert!(svg.contains(r#"<line x1="50.00" y1="0" x2="50.00" y2="100"/>"#));
+        assert!(svg.contains(r#"text-anchor="end" font-size="13">x</text>"#));
+        assert!(svg.contains(r#"dominant-baseline="middle">0</text>"#));
+    }
+
+    #[test]
+    fn tikz_snapshot() {
+        let expected = r"\begin{tikzpicture}
+\definecolor{graphcolor0}{RGB}{255,0,0}
+\definecolor{graphcolor1}{RGB}{0,0,255}
+\definecolor{graphcolor2}{RGB}{0,128,0}
+\definecolor{graphcolor3}{RGB}{0,0,0}
+\fill[white] (0,0) rectangle (2.6458,2.6458);
+\begin{scope}[line cap=round, line join=round]
+\clip (0,0) rectangle (2.6458,2.6458);
+% curve
+\draw[graphcolor0, line width=1.500bp]
+    (0.0000,0.0000) -- (1.3229,1.9844);
+% vector
+\draw[graphcolor1, line width=0.750bp] (1.3229,1.3229) -- (1.9844,1.3229);
+\draw[graphcolor1, line width=0.750bp] (1.9844,1.3229) -- (1.8056,1.2368);
+\draw[graphcolor1, line width=0.750bp] (1.9844,1.3229) -- (1.8056,1.4090);
+% a<b
+\filldraw[draw=graphcolor2, fill=graphcolor2, fill opacity=0.05, line width=1.125bp]
+    (0.6615,0.6615) -- (1.9844,0.6615) -- (1.3229,1.9844) -- cycle;
+% x_1 & y
+\node[graphcolor3, anchor=center, inner sep=0pt, font=\fontsize{9.00bp}{10.80bp}\selectfont] at (1.3229,0.2646) {x\_1 \& y};
+\end{scope}
+\end{tikzpicture}
+";
+        assert_eq!(to_tex(&figure(), 100, 100, TexFlavor::Tikz, false), expected);
+    }
+
+    #[test]
+    fn standalone_tex_is_a_complete_document() {
+        for flavor in [TexFlavor::Tikz, TexFlavor::Pgfplots] {
+            let tex = to_tex(&figure(), 100, 100, flavor, true);
+            assert!(tex.starts_with(r"\documentclass"), "{:?}", flavor);
+            assert!(tex.ends_with("\\end{tikzpicture}\n\\end{document}\n"), "{:?}", flavor);
+        }
+        let pgfplots = to_tex(&figure(), 100, 100, TexFlavor::Pgfplots, false);
+        assert!(pgfplots.contains("xmin=-1, xmax=1"));
+        assert!(pgfplots.contains(r"at (axis cs:0,-0.8) {x\_1 \& y};"));
+    }
+
+    #[test]
+    fn tex_special_characters_are_escaped() {
+        assert_eq!(escape_tex(r"50% {a}_b $c$ #1 ~^\"), r"50\% \{a\}\_b \$c\$ \#1 \textasciitilde{}\textasciicircum{}\textbackslash{}");
+    }
+
+    // CSVの行を項目に分ける (引用符で囲まれた項目に対応する)
+    fn split_csv_line(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                _ => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn csv_round_trips_the_sampled_points() {
+        let mut figure = figure();
+        if let SceneItem::Polygon { name, .. } = &mut figure.scene.items[2] {
+            *name = "a, \"b\"".to_string();
+        }
+        let csv = to_csv(&figure);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("kind,name,index,t,x,y,color,weight"));
+        let rows: Vec<Vec<String>> = lines.map(split_csv_line).collect();
+        assert!(rows.iter().all(|row| row.len() == 8));
+
+        // 各行を (種類, 名前, t, 点) として読み戻すと、シーンの点と一致する
+        let read: Vec<(String, String, Option<f64>, [f64; 2])> = rows.iter()
+            .map(|row| (row[0].clone(), row[1].clone(), row[3].parse().ok(), [row[4].parse().unwrap(), row[5].parse().unwrap()]))
+            .collect();
+        let mut expected = Vec::new();
+        for item in &figure.scene.items {
+            match item {
+                SceneItem::Curve { name, points, params, .. } => {
+                    expected.extend(points.iter().zip(params).map(|(p, t)| ("line".to_string(), name.clone(), Some(*t), *p)));
+                }
+                SceneItem::Vector { name, origin, tip, t, .. } => {
+                    expected.extend([origin, tip].map(|p| ("vector".to_string(), name.clone(), Some(*t), *p)));
+                }
+                SceneItem::Polygon { name, points, .. } => {
+                    expected.extend(points.iter().map(|p| ("polygon".to_string(), name.clone(), None, *p)));
+                }
+                SceneItem::Text { .. } => {}
+            }
+        }
+        assert_eq!(read.len(), expected.len());
+        for (read, expected) in read.iter().zip(&expected) {
+            assert_eq!((&read.0, &read.1, read.2), (&expected.0, &expected.1, expected.2));
+            // NaNは NaN と書き出される
+            assert!(read.3.iter().zip(expected.3).all(|(a, b)| a == &b || (a.is_nan() && b.is_nan())), "{:?}", read);
+        }
+        assert_eq!(rows[0][6..], ["#ff0000".to_string(), "2".to_string()]);
+        assert_eq!(rows[6][6..], ["#008000".to_string(), "1.5".to_string()]);
+    }
+
+    #[test]
+    fn json_round_trips_the_sampled_points() {
+        let figure = figure();
+        let json: serde_json::Value = serde_json::from_str(&to_data_json(&figure)).unwrap();
+        let SceneItem::Curve { points, params, .. } = &figure.scene.items[0] else {
+            unreachable!()
+        };
+        let line = &json["lines"][0];
+        assert_eq!(line["name"], "curve");
+        assert_eq!(line["color"], "#ff0000");
+        assert_eq!(line["weight"], 2.0);
+        let read: Vec<(f64, Option<f64>, f64)> = line["points"].as_array().unwrap().iter()
+            .map(|p| (p["t"].as_f64().unwrap(), p["x"].as_f64(), p["y"].as_f64().unwrap()))
+            .collect();
+        let expected: Vec<(f64, Option<f64>, f64)> = points.iter().zip(params)
+            .map(|(p, t)| (*t, p[0].is_finite().then_some(p[0]), p[1]))
+            .collect();
+        assert_eq!(read, expected);
+
+        assert_eq!(json["vectors"][0], serde_json::json!({
+            "name": "vector", "color": "#0000ff", "weight": 1.0, "t": 0.25, "origin": [0.0, 0.0], "tip": [0.5, 0.0]
+        }));
+        assert_eq!(json["polygons"][0], serde_json::json!({
+            "name": "a<b", "color": "#008000", "weight": 1.5, "points": [[-0.5, -0.5], [0.5, -0.5], [0.0, 0.5]]
+        }));
+        assert_eq!(json.as_object().unwrap().len(), 3);
+    }
+
+    // PNGを読み込んで (幅, 高さ, 1メートルあたりのピクセル数, RGBAの画素) を返す
+    fn decode_png(data: &[u8]) -> (u32, u32, Option<u32>, Vec<u8>) {
+        let mut reader = png::Decoder::new(std::io::Cursor::new(data)).read_info().unwrap();
+        let pixels_per_meter = reader.info().pixel_dims.map(|dims| dims.xppu);
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        (info.width, info.height, pixels_per_meter, rgba)
+    }
+
+    #[test]
+    fn png_has_the_requested_size_and_resolution() {
+        let (width, height, pixels_per_meter, rgba) = decode_png(&to_png(&figure(), 120, 80, 192.0).unwrap());
+        assert_eq!((width, height), (120, 80));
+        assert_eq!(pixels_per_meter, Some((192.0 / 0.0254_f64).round() as u32));
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * width + x) * 4) as usize;
+            [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]]
+        };
+        // 背景は白、多角形の内側は枠線の色を薄くした色
+        assert_eq!(pixel(2, 2), [255, 255, 255, 255]);
+        let inside = pixel(60, 40);
+        assert!(inside[1] > inside[0] && inside[0] < 255, "{:?}", inside);
+        assert!(to_png(&figure(), 0, 80, 96.0).is_err());
+    }
+
+    #[test]
+    fn animations_have_one_frame_per_figure() {
+        let dir = std::env::temp_dir().join(format!("graphapp-export-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let frames = vec![figure(), Figure { view: None, ..figure() }, figure()];
+
+        let gif_path = dir.join("out.gif");
+        write_animation(&frames, 60, 40, 96.0, 20.0, AnimationFormat::Gif, &gif_path).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(std::fs::File::open(&gif_path).unwrap()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (60, 40));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.width, frame.height), (60, 40));
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [5, 5, 5]);
+
+        let apng_path = dir.join("out.png");
+        write_animation(&frames, 60, 40, 96.0, 20.0, AnimationFormat::Apng, &apng_path).unwrap();
+        let reader = png::Decoder::new(std::fs::File::open(&apng_path).unwrap()).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (60, 40));
+        assert_eq!(reader.info().animation_control.map(|control| control.num_frames), Some(3));
+
+        let sequence_path = dir.join("seq.png");
+        write_animation(&frames, 60, 40, 96.0, 20.0, AnimationFormat::PngSequence, &sequence_path).unwrap();
+        for i in 0..3 {
+            let data = std::fs::read(dir.join(format!("seq_{:04}.png", i))).unwrap();
+            let (width, height, _, _) = decode_png(&data);
+            assert_eq!((width, height), (60, 40));
+        }
+
+        assert!(write_animation(&[], 60, 40, 96.0, 20.0, AnimationFormat::Gif, &gif_path).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
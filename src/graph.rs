@@ -1,177 +1,2730 @@
-use boa_engine::JsObject;
 use eframe::{egui, App, Frame};
-use egui_plot::{Line, Plot, PlotPoints, Polygon};
+use egui_plot::{Plot, PlotBounds, PlotPoint, PlotTransform, Points};
 use egui::Color32;
-use boa_engine::{Context as BoaContext, Source, JsValue, JsArgs, NativeFunction, js_string, property::Attribute, property::PropertyKey, JsNativeError};
-use egui::{Ui, Widget, Response, Sense, Pos2, Rect, Stroke, TextEdit, Slider};
-use egui_commonmark;
+use egui::{Ui, Widget, Response, Sense, Pos2, Stroke, TextEdit, Slider};
+#[cfg(not(target_arch = "wasm32"))]
 use egui_extras::syntax_highlighting;
 #[cfg(target_arch = "wasm32")]
 use hframe::Aware;
-use base64::{engine::general_purpose, Engine as _};
 use form_urlencoded::{parse, Serializer};
-use std::sync::{Arc, Mutex};
+#[cfg(target_arch = "wasm32")]
+use std::sync::Mutex;
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::native::{NativeArgs, NativeModule, NativeRegistry, NativeValue};
+use crate::plot;
+use crate::scene::{Color, LineStyle, PolygonStyle, Scene, SceneItem, TextStyle};
+use crate::script::{BoaEngine, HostFn, ScriptCaller, ScriptEngine, ScriptFunction};
+use crate::share;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::export;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 static PENDING_CONTENT: Mutex<Option<String>> = Mutex::new(None);
+#[cfg(target_arch = "wasm32")]
+static PENDING_QUERY: Mutex<Option<String>> = Mutex::new(None); // ブラウザの戻る・進むで変わったURLのクエリ
+
+
+#[cfg(not(target_arch = "wasm32"))]
+use colored::Colorize;
+
+// WASMでのログ出力設定
+#[cfg(target_arch = "wasm32")]
+pub fn setup_logging() {
+    console_log::init_with_level(log::Level::Debug).expect("error initializing log");
+}
+
+// ネイティブでのログ出力設定
+#[cfg(not(target_arch = "wasm32"))]
+pub fn setup_logging() {
+    env_logger::init();
+}
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// スライダ情報を保持する構造体
+#[derive(Clone)]
+pub struct SliderParam {
+    name: String,
+    min: f64,
+    max: f64,
+    step: f64,
+    value: f64,
+    scale: SliderScale,
+    integer: bool, // 整数値のみを取るか
+    playback: SliderPlayback,
+}
+
+// スライダの目盛りの種類
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SliderScale {
+    Linear,
+    Log, // 対数目盛り (min > 0 が必要)
+}
+
+// 刻み幅stepの値を表示するのに必要な小数点以下の桁数
+fn step_decimals(step: f64) -> usize {
+    if step.is_nan() || step <= 0.0 {
+        return 3;
+    }
+    (0..10)
+        .find(|&d| {
+            let scaled = step * 10f64.powi(d as i32);
+            (scaled - scaled.round()).abs() < 1e-6 * scaled.max(1.0)
+        })
+        .unwrap_or(10)
+}
+
+// 範囲スライダ (2つのハンドルを持つスライダ) の情報を保持する構造体
+#[derive(Clone)]
+struct RangeSliderParam {
+    name: String,    // JSで参照する変数名
+    min: f64,
+    max: f64,
+    step: f64,
+    value: [f64; 2], // [下端, 上端]
+}
+
+impl RangeSliderParam {
+    // 2つの値を [下端, 上端] の順に並べ、範囲内に収める
+    fn clamp_range(&self, value: [f64; 2]) -> [f64; 2] {
+        let [lo, hi] = value;
+        [lo.min(hi).clamp(self.min, self.max), hi.max(lo).clamp(self.min, self.max)]
+    }
+}
+
+// スライダなどの最小値・最大値を確かめる (NaNや最小値 > 最大値はエラー)
+fn check_bounds(min: f64, max: f64) -> Result<(), String> {
+    if min.is_nan() || max.is_nan() || min > max {
+        return Err("min must be less than or equal to max".to_string());
+    }
+    Ok(())
+}
+
+// スライダの自動再生モード
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PlayMode {
+    Once,   // maxに達したら停止
+    Loop,   // maxに達したらminに戻る
+    Bounce, // minとmaxの間を往復
+}
+
+impl PlayMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "once" => Some(PlayMode::Once),
+            "loop" => Some(PlayMode::Loop),
+            "bounce" => Some(PlayMode::Bounce),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            PlayMode::Once => "1回",
+            PlayMode::Loop => "ループ",
+            PlayMode::Bounce => "往復",
+        }
+    }
+}
+
+// スライダの自動再生の状態
+#[derive(Clone)]
+struct SliderPlayback {
+    playing: bool,
+    mode: PlayMode,
+    speed: f64,     // 1秒あたりの変化量
+    direction: f64, // 進行方向 (1.0 または -1.0)
+}
+
+impl SliderParam {
+    // 値をスライダ上の位置 (0.0〜1.0) に変換する
+    fn to_normalized(&self, value: f64) -> f64 {
+        let t = match self.scale {
+            SliderScale::Linear => (value - self.min) / (self.max - self.min),
+            SliderScale::Log => (value.ln() - self.min.ln()) / (self.max.ln() - self.min.ln()),
+        };
+        if t.is_finite() { t.clamp(0.0, 1.0) } else { 0.0 }
+    }
+
+    // スライダ上の位置 (0.0〜1.0) を値に変換する
+    fn normalized_to_value(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self.scale {
+            SliderScale::Linear => self.min + t * (self.max - self.min),
+            SliderScale::Log => (self.min.ln() + t * (self.max.ln() - self.min.ln())).exp(),
+        }
+    }
+
+    // キー操作でsteps刻み分だけ値を動かす
+    // 線形目盛りではstep単位、対数目盛り (またはstepなし) ではスライダ全体の1%単位で動かす
+    fn nudge(&self, value: f64, steps: f64) -> f64 {
+        let step = if self.integer { self.step.max(1.0) } else { self.step };
+        let moved = if self.scale == SliderScale::Linear && step > 0.0 {
+            value + steps * step
+        } else {
+            self.normalized_to_value(self.to_normalized(value) + steps * 0.01)
+        };
+        self.quantize(moved)
+    }
+
+    // 表示用に値を整形する (stepの桁数に合わせる)
+    fn format_value(&self, value: f64) -> String {
+        let decimals = if self.integer {
+            0
+        } else if self.scale == SliderScale::Log {
+            // 有効数字4桁程度で表示する
+            (3 - value.abs().log10().floor() as i32).clamp(0, 10) as usize
+        } else {
+            step_decimals(self.step)
+        };
+        format!("{:.*}", decimals, value)
+    }
+
+    // step・integerの設定に合わせて値を丸める
+    // 対数目盛りでは刻み幅が場所によって変わるため、stepは使わない
+    fn quantize(&self, value: f64) -> f64 {
+        let mut value = value;
+        if self.scale == SliderScale::Linear && self.step > 0.0 {
+            value = (value / self.step).round() * self.step;
+        }
+        if self.integer {
+            value = value.round();
+        }
+        value.clamp(self.min, self.max)
+    }
+
+    // 自動再生中のスライダをdt秒分進める
+    fn advance_playback(&mut self, dt: f64) {
+        let range = self.max - self.min;
+        if range <= 0.0 {
+            self.playback.playing = false;
+            return;
+        }
+        let mut value = self.value + self.playback.speed * self.playback.direction * dt;
+        match self.playback.mode {
+            PlayMode::Once => {
+                if value >= self.max {
+                    value = self.max;
+                    self.playback.playing = false;
+                }
+            }
+            PlayMode::Loop => {
+                value = self.min + (value - self.min).rem_euclid(range);
+            }
+            PlayMode::Bounce => {
+                if value > self.max {
+                    value = (2.0 * self.max - value).max(self.min);
+                    self.playback.direction = -1.0;
+                } else if value < self.min {
+                    value = (2.0 * self.min - value).min(self.max);
+                    self.playback.direction = 1.0;
+                }
+            }
+        }
+        self.value = value.clamp(self.min, self.max);
+    }
+}
+
+// チェックボックス情報を保持する構造体
+#[derive(Clone)]
+struct CheckboxParam {
+    name: String,   // JSで参照する変数名
+    label: String,  // チェックボックス横のラベル
+    value: bool,    // 現在値
+}
+
+// カラーピッカー情報を保持する構造体
+#[derive(Clone)]
+struct ColorPickerParam {
+    name: String,   // JSで参照する変数名
+    value: Color32, // 現在の色 (egui::Color32)
+}
+
+// セレクトボックス・ラジオボタン情報を保持する構造体
+#[derive(Clone)]
+struct SelectParam {
+    name: String,         // JSで参照する変数名
+    options: Vec<String>, // 選択肢
+    selected: usize,      // 選択中の選択肢のインデックス
+    radio: bool,          // ラジオボタンとして表示するか
+}
+
+// テキスト入力情報を保持する構造体
+#[derive(Clone)]
+struct TextInputParam {
+    name: String,  // JSで参照する変数名
+    value: String, // 現在の文字列
+}
+
+// 数値入力欄 (範囲なし) の情報を保持する構造体
+#[derive(Clone)]
+struct NumberParam {
+    name: String, // JSで参照する変数名
+    step: f64,    // ドラッグ時の変化量
+    value: f64,
+}
+
+// ボタン情報を保持する構造体
+#[derive(Clone)]
+struct ButtonParam {
+    label: String,      // ボタンのラベル
+    callback: ScriptFunction, // クリック時に呼び出す関数
+}
+
+// パラメータウィンドウに並べるUI要素の種類
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ControlKind {
+    Slider,
+    RangeSlider,
+    Checkbox,
+    ColorPicker,
+    Select,
+    TextInput,
+    Number,
+    Button,
+    DragPoint,
+}
+
+// パラメータウィンドウに並べるUI要素 (スクリプトでの宣言順に保持する)
+#[derive(Clone)]
+struct ControlEntry {
+    kind: ControlKind,
+    index: usize,                // 種類ごとの一覧でのインデックス
+    label: Option<String>,       // 表示名 (省略時は変数名)
+    description: Option<String>, // ツールチップに表示する説明
+    group: Option<String>,       // 折りたたみグループの名前
+}
+
+// UI要素をパラメータウィンドウの並びに追加する (paramsの label / description / group を読み取る)
+fn push_control_entry(
+    layout: &RefCell<Vec<ControlEntry>>,
+    kind: ControlKind,
+    index: usize,
+    params: &NativeValue,
+) {
+    let read = |key: &str| -> Option<String> {
+        let value = params.get(key);
+        (!value.is_undefined()).then(|| value.to_string())
+    };
+    layout.borrow_mut().push(ControlEntry {
+        kind,
+        index,
+        label: read("label"),
+        description: read("description"),
+        group: read("group"),
+    });
+}
+
+// [r, g, b] の配列を色として読む (数値でない成分は既定の色の値を使う)
+fn to_color(value: &NativeValue, default: Color) -> Color {
+    match value.as_array() {
+        Some(items) if items.len() >= 3 => {
+            let channel = |i: usize| items[i].as_f64().map_or(default.0[i], |v| v as u8);
+            Color::rgb(channel(0), channel(1), channel(2))
+        }
+        _ => default,
+    }
+}
+
+// エラーを端末と出力ログに書き出す
+fn push_stderr(log_output: &RefCell<Vec<LogEntry>>, msg: String) {
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("[JS stderr]: {}", msg.clone().red());
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::error_1(&msg.clone().into());
+    log_output.borrow_mut().push(LogEntry {
+        log_type: LogType::Stderr,
+        message: msg,
+    });
+}
+
+// UI要素の値 (プリセットや保存用)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ControlValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    Pair([f64; 2]),  // 範囲スライダ・ドラッグ点
+    Color([u8; 3]),  // カラーピッカー
+}
+
+impl ControlValue {
+    // コマンドラインで指定された値 (--set name=value のvalue) を、現在の値kindと同じ型として読む
+    // 真偽値は true/false、2つ組は "x,y"、色は "r,g,b" (0〜255) または "#rrggbb"
+    #[cfg(not(target_arch = "wasm32"))]
+    fn parse_arg(text: &str, kind: &ControlValue) -> Result<ControlValue, String> {
+        let number = |s: &str| s.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+        match kind {
+            ControlValue::Bool(_) => match text {
+                "true" => Ok(ControlValue::Bool(true)),
+                "false" => Ok(ControlValue::Bool(false)),
+                _ => Err("true か false を指定してください".to_string()),
+            },
+            ControlValue::Number(_) => number(text)
+                .map(ControlValue::Number)
+                .ok_or_else(|| "数値を指定してください".to_string()),
+            ControlValue::Text(_) => Ok(ControlValue::Text(text.to_string())),
+            ControlValue::Pair(_) => match text.split(',').map(number).collect::<Option<Vec<f64>>>().as_deref() {
+                Some(&[x, y]) => Ok(ControlValue::Pair([x, y])),
+                _ => Err("\"x,y\" の形で指定してください".to_string()),
+            },
+            ControlValue::Color(_) => {
+                if let Some(hex) = text.strip_prefix('#') {
+                    return match u32::from_str_radix(hex, 16) {
+                        Ok(rgb) if hex.len() == 6 => Ok(ControlValue::Color([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])),
+                        _ => Err("\"#rrggbb\" の形で指定してください".to_string()),
+                    };
+                }
+                let channels: Option<Vec<u8>> = text.split(',').map(|c| c.trim().parse::<u8>().ok()).collect();
+                match channels.as_deref() {
+                    Some(&[r, g, b]) => Ok(ControlValue::Color([r, g, b])),
+                    _ => Err("\"r,g,b\" (0〜255の整数) または \"#rrggbb\" の形で指定してください".to_string()),
+                }
+            }
+        }
+    }
+
+    // selfからtoへ割合t (0.0〜1.0) だけ進んだ値を返す
+    // 補間できない値 (真偽値・文字列) は切り替え先の値をそのまま返す
+    fn lerp(&self, to: &ControlValue, t: f64) -> ControlValue {
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+        match (self, to) {
+            (ControlValue::Number(a), ControlValue::Number(b)) => ControlValue::Number(mix(*a, *b)),
+            (ControlValue::Pair(a), ControlValue::Pair(b)) => ControlValue::Pair([mix(a[0], b[0]), mix(a[1], b[1])]),
+            (ControlValue::Color(a), ControlValue::Color(b)) => {
+                ControlValue::Color(std::array::from_fn(|i| mix(a[i] as f64, b[i] as f64).round() as u8))
+            }
+            _ => to.clone(),
+        }
+    }
+}
+
+impl From<f64> for ControlValue {
+    fn from(value: f64) -> Self {
+        ControlValue::Number(value)
+    }
+}
+
+impl From<bool> for ControlValue {
+    fn from(value: bool) -> Self {
+        ControlValue::Bool(value)
+    }
+}
+
+impl From<&str> for ControlValue {
+    fn from(value: &str) -> Self {
+        ControlValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for ControlValue {
+    fn from(value: String) -> Self {
+        ControlValue::Text(value)
+    }
+}
+
+impl From<[f64; 2]> for ControlValue {
+    fn from(value: [f64; 2]) -> Self {
+        ControlValue::Pair(value)
+    }
+}
+
+impl From<[u8; 3]> for ControlValue {
+    fn from(value: [u8; 3]) -> Self {
+        ControlValue::Color(value)
+    }
+}
+
+// 名前付きのUI要素の値の組
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Preset {
+    name: String,
+    values: BTreeMap<String, ControlValue>,
+    #[serde(skip)]
+    from_code: bool, // addPresetでスクリプトから定義されたか
+}
+
+// プリセット切り替え時のアニメーション
+struct PresetTransition {
+    from: BTreeMap<String, ControlValue>,
+    to: BTreeMap<String, ControlValue>,
+    progress: f64, // 0.0〜1.0
+}
+
+// プリセット切り替えのアニメーションにかける時間 (秒)
+const PRESET_TRANSITION_SECONDS: f64 = 0.6;
+
+// ドラッグ可能な点の情報を保持する構造体
+#[derive(Clone)]
+struct DragPointParam {
+    name: String,                 // JSで参照する変数名
+    value: [f64; 2],              // 現在の座標
+    snap: f64,                    // スナップする格子の間隔 (0以下なら無効)
+    constrain: Option<ScriptFunction>,  // 座標を補正する関数 (x, y) => [x', y']
+    color: Color32,               // ハンドルの色
+}
+
+// アニメーション変数の情報を保持する構造体
+#[derive(Clone)]
+struct AnimationParam {
+    name: String,  // JSで参照する変数名
+    min: f64,
+    max: f64,
+    speed: f64,    // 1秒あたりの変化量
+    looping: bool, // maxに達したらminに戻るか
+    value: f64,
+}
+
+impl AnimationParam {
+    // dt秒分だけ値を進める
+    fn advance(&mut self, dt: f64) {
+        let range = self.max - self.min;
+        if range <= 0.0 {
+            self.value = self.min;
+            return;
+        }
+        self.value += self.speed * dt;
+        if self.looping {
+            self.value = self.min + (self.value - self.min).rem_euclid(range);
+        } else {
+            self.value = self.value.clamp(self.min, self.max);
+        }
+    }
+}
+
+// アニメーションの再生状態
+struct AnimationState {
+    playing: bool,
+    speed: f64, // 再生速度の倍率
+    time: f64,  // 再生開始からの経過時間 (秒)
+    frame: u64, // 再生開始からのフレーム数
+}
+
+impl Default for AnimationState {
+    fn default() -> Self {
+        Self { playing: true, speed: 1.0, time: 0.0, frame: 0 }
+    }
+}
+
+// シミュレーション (init/update) の状態
+struct SimulationState {
+    enabled: bool,    // スクリプトにupdate関数が定義されているか
+    running: bool,
+    timestep: f64,    // 1ステップの時間 (秒)
+    accumulator: f64, // まだステップに消化していない経過時間 (秒)
+    steps: u64,       // リセットからのステップ数
+}
+
+impl Default for SimulationState {
+    fn default() -> Self {
+        Self { enabled: false, running: true, timestep: 1.0 / 60.0, accumulator: 0.0, steps: 0 }
+    }
+}
+
+// 1フレームで実行するシミュレーションステップの上限 (処理落ち時に追いつこうとして固まるのを防ぐ)
+const MAX_SIMULATION_STEPS_PER_FRAME: u32 = 8;
 
+// コマ送り1回で進める時間 (秒)
+const ANIMATION_STEP_DT: f64 = 1.0 / 60.0;
+
+// 履歴に記録する状態 (コードとUI要素の値)
+#[derive(Clone, Debug, PartialEq)]
+struct HistoryState {
+    code: String,
+    values: BTreeMap<String, ControlValue>,
+}
+
+// 履歴の1項目
+struct HistoryEntry {
+    label: String,       // 履歴ウィンドウに表示する説明
+    state: HistoryState,
+    time: f64,           // 最後に更新した時刻 (秒)
+}
+
+// 元に戻す・やり直しのための履歴
+#[derive(Default)]
+struct History {
+    entries: Vec<HistoryEntry>,
+    current: usize, // 現在の状態に対応する項目のインデックス
+    moved: bool,    // 元に戻す・やり直しで移動した直後か (移動先の項目には次の変更をまとめない)
+}
+
+// 同じ種類の変更がこの時間 (秒) 以内に続いた場合は1つの履歴にまとめる
+const HISTORY_COALESCE_SECONDS: f64 = 1.0;
+
+// 保持する履歴の最大数
+const MAX_HISTORY_ENTRIES: usize = 200;
+
+impl History {
+    fn current_state(&self) -> Option<&HistoryState> {
+        self.entries.get(self.current).map(|e| &e.state)
+    }
+
+    // 新しい状態を記録する。現在位置より先 (やり直し用) の項目は破棄する
+    fn record(&mut self, label: String, state: HistoryState, time: f64) {
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.current + 1);
+        }
+        let moved = std::mem::take(&mut self.moved);
+        // 最初の項目と、元に戻した先の項目はまとめずに残す
+        if !moved
+            && self.entries.len() > 1
+            && let Some(last) = self.entries.last_mut()
+            && last.label == label
+            && time - last.time < HISTORY_COALESCE_SECONDS
+        {
+            last.state = state;
+            last.time = time;
+            return;
+        }
+        self.entries.push(HistoryEntry { label, state, time });
+        if self.entries.len() > MAX_HISTORY_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len() - 1;
+    }
+
+    // index番目の項目に移動する
+    fn go_to(&mut self, index: usize) -> Option<&HistoryState> {
+        let entry = self.entries.get(index)?;
+        self.current = index;
+        self.moved = true;
+        Some(&entry.state)
+    }
+
+    fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    fn can_redo(&self) -> bool {
+        self.current + 1 < self.entries.len()
+    }
+}
+
+// URLで共有する状態
+#[derive(Default, Serialize, Deserialize)]
+struct SharedState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    values: Option<BTreeMap<String, ControlValue>>, // UI要素の値
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    view: Option<[f64; 4]>,                         // プロットの表示範囲 [x最小, y最小, x最大, y最大]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,                         // 選択中のプリセット名
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    presets: Vec<Preset>,                           // 画面から保存したプリセット
+}
+
+// SharedStateが使うクエリパラメータ名 (これ以外のパラメータはそのまま残す)
+// "s" が現在の形式、それ以外は以前の形式
+const SHARED_QUERY_KEYS: [&str; 5] = ["s", "code", "values", "view", "preset"];
+
+impl SharedState {
+    // クエリ文字列から読み込む
+    // 以前の形式のパラメータは読めない項目を無視する
+    fn from_query(query: &str) -> Result<Self, String> {
+        let mut state = Self::default();
+        for (k, v) in parse(query.trim_start_matches('?').as_bytes()) {
+            match &*k {
+                "s" => return share::decode(&v),
+                "code" => state.code = share::decode_legacy(&v).filter(|code| !code.trim().is_empty()),
+                "values" => state.values = share::decode_legacy(&v).and_then(|json| serde_json::from_str(&json).ok()),
+                "view" => {
+                    let n: Vec<f64> = v.split(',').filter_map(|x| x.parse().ok()).collect();
+                    if let [min_x, min_y, max_x, max_y] = n[..]
+                        && min_x < max_x && min_y < max_y
+                    {
+                        state.view = Some([min_x, min_y, max_x, max_y]);
+                    }
+                }
+                "preset" => state.preset = Some(v.into_owned()),
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    // 共有リンク (URL全体・クエリ・符号化した文字列のいずれか) から読み込む
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_link(link: &str) -> Result<Self, String> {
+        let link = link.trim();
+        let link = link.split_once('#').map_or(link, |(before, _)| before);
+        match link.split_once('?') {
+            Some((_, query)) => Self::from_query(query),
+            None if link.contains('=') => Self::from_query(link),
+            None => share::decode(link),
+        }
+    }
+
+    // クエリ文字列に書き出す。queryに含まれる他のパラメータは残す
+    fn to_query(&self, query: &str) -> String {
+        let mut ser = Serializer::new(String::new());
+        for (k, v) in parse(query.trim_start_matches('?').as_bytes()) {
+            if !SHARED_QUERY_KEYS.contains(&&*k) {
+                ser.append_pair(&k, &v);
+            }
+        }
+        ser.append_pair("s", &share::encode(self));
+        ser.finish()
+    }
+
+    // Web版で開ける共有リンク
+    #[cfg(not(target_arch = "wasm32"))]
+    fn to_link(&self) -> String {
+        format!("{}?{}", share::SHARE_BASE_URL, self.to_query(""))
+    }
+}
+
+// URLを書き換える最短の間隔 (秒)。ブラウザの履歴APIの呼び出し回数制限を避ける
+#[cfg(target_arch = "wasm32")]
+const URL_UPDATE_INTERVAL: f64 = 0.5;
+
+// 履歴の説明文を作る (変更されたUI要素の名前を並べる)
+fn history_label(from: &HistoryState, to: &HistoryState) -> String {
+    if from.code != to.code {
+        return "コード編集".to_string();
+    }
+    let changed: Vec<&str> = to.values.iter()
+        .filter(|(name, value)| from.values.get(*name) != Some(*value))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    format!("パラメータ変更: {}", changed.join(", "))
+}
+
+// ログメッセージの種類と内容
+#[derive(Clone, Debug, PartialEq)] // PartialEqを追加して比較できるようにする
+enum LogType {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Debug, PartialEq)] // PartialEqを追加
+struct LogEntry {
+    log_type: LogType,
+    message: String,
+}
+
+// アプリケーションの状態を保持する構造体
+pub struct ParametricPlotApp {
+    sliders: Vec<SliderParam>,
+    checkboxes: Vec<CheckboxParam>, // チェックボックス一覧を追加
+    color_pickers: Vec<ColorPickerParam>, // カラーピッカー一覧を追加
+    range_sliders: Vec<RangeSliderParam>, // 範囲スライダ一覧
+    layout: Vec<ControlEntry>, // パラメータウィンドウでの並び順
+    presets: Vec<Preset>, // プリセット一覧
+    selected_preset: Option<String>, // 最後に適用・保存したプリセット名
+    new_preset_name: String, // プリセット保存欄の入力
+    animate_presets: bool, // プリセット切り替えをアニメーションさせるか
+    preset_transition: Option<PresetTransition>,
+    selects: Vec<SelectParam>, // セレクトボックス・ラジオボタン一覧
+    text_inputs: Vec<TextInputParam>, // テキスト入力一覧
+    numbers: Vec<NumberParam>, // 数値入力欄一覧
+    buttons: Vec<ButtonParam>, // ボタン一覧
+    drag_points: Vec<DragPointParam>, // ドラッグ可能な点の一覧
+    dragging_point: Option<usize>, // ドラッグ中の点のインデックス
+    last_plot_transform: Option<PlotTransform>, // 前フレームのプロット座標変換 (ハンドルの当たり判定用)
+    animations: Vec<AnimationParam>, // アニメーション変数の一覧
+    animate: bool, // スクリプトが animate = true を指定しているか
+    animation: AnimationState,
+    simulation: SimulationState,
+    engine: Box<dyn ScriptEngine>, // スクリプトの実行環境 (既定はBoa)
+    js_code_evaluated: bool,
+    native: NativeRegistry, // 埋め込む側がRustで登録した関数
+    scene: Rc<RefCell<Scene>>, // draw()で追加された図形
+    js_code: String, // JavaScriptエディタ用
+    last_js_code: String, // 前回実行したJSコード
+    api_docs_content: String,
+    log_output: Rc<RefCell<Vec<LogEntry>>>,
+    commonmark_cache: egui_commonmark::CommonMarkCache,
+    last_hover_coordinate: Option<[f64; 2]>, // 前回onHoverに渡したプロット座標
+    history: History, // 元に戻す・やり直しの履歴
+    pending_control_values: Option<BTreeMap<String, ControlValue>>, // 次のコード再評価後に反映するUI要素の値
+    redraw_requested: bool, // 次のフレームで再描画する
+    plot_bounds: Option<[f64; 4]>, // 現在のプロットの表示範囲 [x最小, y最小, x最大, y最大]
+    pending_plot_bounds: Option<[f64; 4]>, // 次のフレームで設定する表示範囲
+    reset_plot_view: bool, // 次のフレームで表示範囲を自動に戻す
+    plot_options: PlotOptions,
+    metadata: DocumentMetadata,
+    #[cfg(not(target_arch = "wasm32"))]
+    document_path: Option<PathBuf>, // 開いているドキュメントファイル
+    #[cfg(not(target_arch = "wasm32"))]
+    script_watch: Option<ScriptWatch>, // --watch で監視しているスクリプトファイル
+    #[cfg(not(target_arch = "wasm32"))]
+    export_settings: ExportSettings,
+    #[cfg(not(target_arch = "wasm32"))]
+    record_settings: RecordSettings,
+    #[cfg(target_arch = "wasm32")]
+    last_shared_query: String, // 最後にURLへ書き込んだクエリ
+    #[cfg(target_arch = "wasm32")]
+    last_url_update: f64, // 最後にURLを書き換えた時刻 (秒)
+    #[cfg(not(target_arch = "wasm32"))]
+    share_link_input: String, // 共有リンクを開く欄の入力
+}
+
+// 起動時・新規ドキュメントのスクリプト
+const DEFAULT_JS_CODE: &str = r#"
+function setup() {
+    addSlider('radius', { min: 0.5, max: 5.0, step: 0.001, default: 1.0 });
+    addColorpicker('lineColor', { default: [255, 0, 0] });
+    addCheckbox('show', '図形を表示する', { default: true });
+}
+
+function draw() {
+    if (show) {
+        // 円の描画
+        addParametricGraph(
+            '円',
+            function(t) { return [radius * Math.cos(t), radius * Math.sin(t)]; },
+            { min: 0, max: 2 * Math.PI, num_points: 100 },
+            { color: lineColor, weight: 2.0 }
+        );
+
+        // 三角形の描画
+        addPolygon(
+            '三角形',
+            [[radius, 0], [radius * Math.cos(2*Math.PI/3), radius * Math.sin(2*Math.PI/3)], [radius * Math.cos(4*Math.PI/3), radius * Math.sin(4*Math.PI/3)]],
+            { color: lineColor, weight: 2.0 }
+        );
+    }
+}
+"#;
+
+// ドキュメントのメタデータ
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct DocumentMetadata {
+    title: String,
+    author: String,
+    description: String,
+}
+
+// プロットの表示設定
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct PlotOptions {
+    show_axes: bool,
+    show_grid: bool,
+    data_aspect: Option<f32>, // 縦横比 (Noneなら揃えない)
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        Self { show_axes: true, show_grid: true, data_aspect: Some(1.0) }
+    }
+}
+
+// ドキュメントファイル (.graph.json) の内容
+#[derive(Clone, Serialize, Deserialize)]
+struct GraphDocument {
+    format: String, // 常にDOCUMENT_FORMAT
+    version: u32,
+    #[serde(default)]
+    metadata: DocumentMetadata,
+    script: String,
+    #[serde(default)]
+    values: BTreeMap<String, ControlValue>, // UI要素の値
+    #[serde(default)]
+    presets: Vec<Preset>,                   // 画面から保存したプリセット
+    #[serde(default)]
+    selected_preset: Option<String>,
+    #[serde(default)]
+    plot: PlotOptions,
+    #[serde(default)]
+    view: Option<[f64; 4]>,                 // プロットの表示範囲 [x最小, y最小, x最大, y最大]
+}
+
+// ドキュメントファイルの識別子とバージョン
+const DOCUMENT_FORMAT: &str = "graphapp";
+const DOCUMENT_VERSION: u32 = 1;
+
+// ドキュメントファイルの拡張子
+#[cfg(not(target_arch = "wasm32"))]
+const DOCUMENT_EXTENSION: &str = "graph.json";
+
+impl GraphDocument {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_json(json: &str) -> Result<Self, String> {
+        let document: Self = serde_json::from_str(json).map_err(|e| format!("ドキュメントを読み込めません: {}", e))?;
+        if document.format != DOCUMENT_FORMAT {
+            return Err(format!("グラフのドキュメントではありません (format: {})", document.format));
+        }
+        if document.version > DOCUMENT_VERSION {
+            return Err(format!("新しいバージョンのドキュメントには対応していません (version: {})", document.version));
+        }
+        Ok(document)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("ドキュメントはJSONに変換できる")
+    }
+}
+
+// 外部のエディタで編集しているスクリプトファイルの監視 (--watch)
+#[cfg(not(target_arch = "wasm32"))]
+struct ScriptWatch {
+    path: PathBuf,
+    contents: Option<String>, // 最後に読み込んだ内容
+    error: Option<String>,    // 読み込めなかった場合のエラー
+    last_poll: f64,           // 最後に確認した時刻 (秒)
+}
+
+// 監視しているファイルを確認する間隔 (秒)
+#[cfg(not(target_arch = "wasm32"))]
+const WATCH_POLL_INTERVAL: f64 = 0.5;
+
+// 図を書き出すときの画像の大きさと解像度
+#[cfg(not(target_arch = "wasm32"))]
+struct ExportSettings {
+    width: u32,
+    height: u32,
+    dpi: f64, // PNGの解像度 (線の太さ・文字の大きさもこれに合わせて拡大する)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            dpi: export::BASE_DPI,
+        }
+    }
+}
+
+// アニメーションとして記録するときに変化させるもの
+#[cfg(not(target_arch = "wasm32"))]
+pub enum RecordSource {
+    Time,                                      // time (と addAnimation の値・シミュレーション) を進める
+    Sweep { name: String, from: f64, to: f64 }, // スライダの値をfromからtoまで動かす
+}
+
+// アニメーションの記録の設定
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Recording {
+    pub source: RecordSource,
+    pub frames: usize,
+    pub fps: f64,
+}
+
+// 画面からアニメーションを書き出すときの設定
+#[cfg(not(target_arch = "wasm32"))]
+struct RecordSettings {
+    sweep: Option<String>, // 動かすスライダ (Noneなら時間を進める)
+    from: f64,
+    to: f64,
+    frames: usize,
+    fps: f64,
+    format: export::AnimationFormat,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for RecordSettings {
+    fn default() -> Self {
+        Self {
+            sweep: None,
+            from: 0.0,
+            to: 1.0,
+            frames: 60,
+            fps: 30.0,
+            format: export::AnimationFormat::Gif,
+        }
+    }
+}
+
+// 図の書き出し形式
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Svg,
+    Png,
+    Tex(export::TexFlavor),
+    Csv,
+    Json,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Png => "png",
+            ExportFormat::Tex(_) => "tex",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+// セッションをまたいで保存する状態
+// ウィンドウの配置はeframeがeguiのメモリごと保存する
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    document: GraphDocument,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(default)]
+    path: Option<PathBuf>, // 開いているドキュメントファイル
+}
+
+// eframeのストレージでセッションを保存するキー
+const SESSION_STORAGE_KEY: &str = "graphapp_session";
+
+impl Default for ParametricPlotApp {
+    fn default() -> Self {
+        // URLで共有された状態 (コード・UI要素の値・表示範囲・プリセット)
+        #[cfg(target_arch = "wasm32")]
+        let shared = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .and_then(|search| match SharedState::from_query(&search) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    web_sys::console::error_1(&e.into());
+                    None
+                }
+            })
+            .unwrap_or_default();
+        #[cfg(not(target_arch = "wasm32"))]
+        let shared = SharedState::default();
+        let app = Self::with_shared_state(shared);
+        #[cfg(target_arch = "wasm32")]
+        update_monaco(&app.js_code);
+        app
+    }
+}
+
+impl ParametricPlotApp {
+    // URL・エディタ (Monaco)・保存されたセッションに触れずにアプリを作る
+    // 他のアプリへの埋め込み (GraphPlotter) やテストで使う
+    pub fn headless() -> Self {
+        Self::with_shared_state(SharedState::default())
+    }
+
+    // 共有された状態 (コードがなければ既定のスクリプト) から作る
+    fn with_shared_state(shared: SharedState) -> Self {
+        let default_js_code = shared.code.unwrap_or_else(|| DEFAULT_JS_CODE.to_string());
+        Self {
+            sliders: Vec::new(),
+            checkboxes: Vec::new(),
+            color_pickers: Vec::new(),
+            range_sliders: Vec::new(),
+            layout: Vec::new(),
+            presets: shared.presets,
+            selected_preset: shared.preset,
+            new_preset_name: String::new(),
+            animate_presets: true,
+            preset_transition: None,
+            selects: Vec::new(),
+            text_inputs: Vec::new(),
+            numbers: Vec::new(),
+            buttons: Vec::new(),
+            drag_points: Vec::new(),
+            dragging_point: None,
+            last_plot_transform: None,
+            animations: Vec::new(),
+            animate: false,
+            animation: AnimationState::default(),
+            simulation: SimulationState::default(),
+            engine: Box::new(BoaEngine::new()),
+            native: NativeRegistry::default(),
+            js_code_evaluated: false,
+            scene: Rc::new(RefCell::new(Scene::default())),
+            js_code: default_js_code.clone(),
+            last_js_code: default_js_code,
+            api_docs_content: include_str!("../doc/api.md").to_string(),
+            log_output: Rc::new(RefCell::new(Vec::new())),
+            commonmark_cache: egui_commonmark::CommonMarkCache::default(),
+            last_hover_coordinate: None,
+            history: History::default(),
+            pending_control_values: shared.values,
+            redraw_requested: false,
+            plot_bounds: None,
+            pending_plot_bounds: shared.view,
+            reset_plot_view: false,
+            plot_options: PlotOptions::default(),
+            metadata: DocumentMetadata::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            document_path: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            script_watch: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            export_settings: ExportSettings::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            record_settings: RecordSettings::default(),
+            #[cfg(target_arch = "wasm32")]
+            last_shared_query: String::new(),
+            #[cfg(target_arch = "wasm32")]
+            last_url_update: 0.0,
+            #[cfg(not(target_arch = "wasm32"))]
+            share_link_input: String::new(),
+        }
+    }
+
+    // 前回のセッションを復元してアプリを作る (共有リンクで開いた場合はリンクの内容を優先する)
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let mut app = Self::default();
+        #[cfg(target_arch = "wasm32")]
+        let opened_from_link = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .and_then(|search| SharedState::from_query(&search).ok())
+            .is_some_and(|state| state.code.is_some());
+        #[cfg(not(target_arch = "wasm32"))]
+        let opened_from_link = false;
+        if !opened_from_link {
+            let session = cc.storage
+                .and_then(|storage| storage.get_string(SESSION_STORAGE_KEY))
+                .and_then(|json| serde_json::from_str::<SavedSession>(&json).ok());
+            if let Some(session) = session {
+                app.restore_session(session);
+            }
+        }
+        app
+    }
+
+    fn restore_session(&mut self, session: SavedSession) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.document_path = session.path;
+        }
+        self.load_document(session.document);
+    }
+
+    // 現在の状態をドキュメントにまとめる
+    fn to_document(&self) -> GraphDocument {
+        GraphDocument {
+            format: DOCUMENT_FORMAT.to_string(),
+            version: DOCUMENT_VERSION,
+            metadata: self.metadata.clone(),
+            script: self.js_code.clone(),
+            values: self.control_values(),
+            presets: self.presets.iter().filter(|p| !p.from_code).cloned().collect(),
+            selected_preset: self.selected_preset.clone(),
+            plot: self.plot_options.clone(),
+            view: self.plot_bounds,
+        }
+    }
+
+    // ドキュメントの内容で置き換える (UI要素の値はスクリプトの再評価後に反映する)
+    fn load_document(&mut self, document: GraphDocument) {
+        self.js_code = document.script;
+        #[cfg(target_arch = "wasm32")]
+        update_monaco(&self.js_code);
+        self.js_code_evaluated = false;
+        self.pending_control_values = Some(document.values);
+        self.pending_plot_bounds = document.view;
+        self.reset_plot_view = document.view.is_none();
+        self.presets = document.presets;
+        self.selected_preset = document.selected_preset;
+        self.preset_transition = None;
+        self.plot_options = document.plot;
+        self.metadata = document.metadata;
+        self.history = History::default();
+    }
+
+    // 既定のスクリプトで新しいドキュメントを始める (保存されたセッションも次回の保存で置き換わる)
+    fn new_document(&mut self) {
+        self.load_document(GraphDocument {
+            format: DOCUMENT_FORMAT.to_string(),
+            version: DOCUMENT_VERSION,
+            metadata: DocumentMetadata::default(),
+            script: DEFAULT_JS_CODE.to_string(),
+            values: BTreeMap::new(),
+            presets: Vec::new(),
+            selected_preset: None,
+            plot: PlotOptions::default(),
+            view: None,
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.document_path = None;
+        }
+    }
+
+    // ドキュメントファイルを開く
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_document(&mut self, path: &Path) -> Result<(), String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let document = GraphDocument::from_json(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.load_document(document);
+        self.document_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    // スクリプトファイルを読み込み、以降は変更されるたびに読み込み直す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_script(&mut self, path: PathBuf) {
+        self.script_watch = Some(ScriptWatch {
+            path,
+            contents: None,
+            error: None,
+            last_poll: f64::NEG_INFINITY,
+        });
+        self.poll_script_watch(0.0);
+    }
+
+    // 監視しているファイルが変更されていればコードを置き換える (UI要素の値は保つ)
+    #[cfg(not(target_arch = "wasm32"))]
+    fn poll_script_watch(&mut self, now: f64) {
+        let Some(watch) = &mut self.script_watch else {
+            return;
+        };
+        if now - watch.last_poll < WATCH_POLL_INTERVAL {
+            return;
+        }
+        watch.last_poll = now;
+        match std::fs::read_to_string(&watch.path) {
+            Ok(contents) => {
+                watch.error = None;
+                if watch.contents.as_ref() == Some(&contents) {
+                    return;
+                }
+                watch.contents = Some(contents.clone());
+                if self.js_code_evaluated && self.pending_control_values.is_none() {
+                    self.pending_control_values = Some(self.control_values());
+                }
+                self.js_code = contents;
+            }
+            Err(e) => watch.error = Some(format!("{}: {}", watch.path.display(), e)),
+        }
+    }
+
+    // スクリプトファイルを読み込む
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_script(&mut self, path: &Path) -> Result<(), String> {
+        let code = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.set_js_code(code);
+        Ok(())
+    }
+
+    // ウィンドウを開かずにsetup()とdraw()を実行し、描画内容を図として取り出す
+    // overridesはコマンドラインで指定されたUI要素の値 (名前, 値)
+    // スクリプトでエラーが起きた場合はそのメッセージを返す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_headless(&mut self, overrides: &[(String, String)]) -> Result<export::Figure, String> {
+        self.prepare_headless(overrides)?;
+        self.drawn_figure(self.pending_plot_bounds)
+    }
+
+    // ウィンドウを開かずにsetup()を実行し、コマンドラインで指定されたUI要素の値を反映する
+    // スクリプトの読み込みやsetup()でエラーが起きていればそのメッセージを返す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn prepare_headless(&mut self, overrides: &[(String, String)]) -> Result<(), String> {
+        self.evaluate_script();
+        let errors = self.script_errors();
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        let defined = self.control_values();
+        let mut values = BTreeMap::new();
+        for (name, text) in overrides {
+            if !defined.contains_key(name) {
+                return Err(format!("UI要素 '{}' はスクリプトで定義されていません", name));
+            }
+            let value = ControlValue::parse_arg(text, &defined[name])
+                .and_then(|value| self.check_control_value(name, &value).map(|_| value))
+                .map_err(|e| format!("UI要素 '{}' の値 '{}' が不正です: {}", name, text, e))?;
+            values.insert(name.clone(), value);
+        }
+        self.apply_control_values(&values);
+        Ok(())
+    }
+
+    // 値がUI要素の取りうる範囲 (スライダの最小値〜最大値、選択肢) に入っているか確かめる
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_control_value(&self, name: &str, value: &ControlValue) -> Result<(), String> {
+        let in_range = |v: f64, min: f64, max: f64| {
+            if (min..=max).contains(&v) { Ok(()) } else { Err(format!("{}〜{} の範囲で指定してください", min, max)) }
+        };
+        if let (Some(slider), ControlValue::Number(v)) = (self.sliders.iter().find(|s| s.name == name), value) {
+            return in_range(*v, slider.min, slider.max);
+        }
+        if let (Some(range), ControlValue::Pair([lo, hi])) = (self.range_sliders.iter().find(|r| r.name == name), value) {
+            in_range(*lo, range.min, range.max)?;
+            return in_range(*hi, range.min, range.max);
+        }
+        if let (Some(select), ControlValue::Text(option)) = (self.selects.iter().find(|s| s.name == name), value)
+            && !select.options.contains(option)
+        {
+            return Err(format!("選択肢 ({}) のいずれかを指定してください", select.options.join(", ")));
+        }
+        Ok(())
+    }
+
+    // draw()を実行して描画内容を図にする。スクリプトでエラーが起きていればそのメッセージを返す
+    #[cfg(not(target_arch = "wasm32"))]
+    fn drawn_figure(&mut self, view: Option<[f64; 4]>) -> Result<export::Figure, String> {
+        self.run_draw();
+        let errors = self.script_errors();
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        Ok(self.figure(view))
+    }
+
+    // setup()直後の状態から、1コマずつ時間を進めるかスライダを動かしながら描画内容を記録する
+    // viewを指定しなければドキュメントに保存された表示範囲を使う
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn record_animation(&mut self, recording: &Recording, view: Option<[f64; 4]>) -> Result<Vec<export::Figure>, String> {
+        if recording.frames == 0 {
+            return Err("コマ数は1以上にしてください".to_string());
+        }
+        if !(recording.fps > 0.0 && recording.fps.is_finite()) {
+            return Err("フレームレートは正の数にしてください".to_string());
+        }
+        let sweep = match &recording.source {
+            RecordSource::Time => None,
+            RecordSource::Sweep { name, from, to } => {
+                let index = self.sliders.iter().position(|s| &s.name == name)
+                    .ok_or_else(|| format!("スライダ '{}' はスクリプトで定義されていません", name))?;
+                Some((index, *from, *to))
+            }
+        };
+        let view = view.or(self.pending_plot_bounds);
+        let dt = 1.0 / recording.fps;
+        let mut figures = Vec::with_capacity(recording.frames);
+        for i in 0..recording.frames {
+            match sweep {
+                Some((index, from, to)) => {
+                    let ratio = if recording.frames > 1 { i as f64 / (recording.frames - 1) as f64 } else { 0.0 };
+                    let slider = &mut self.sliders[index];
+                    slider.value = slider.quantize(from + (to - from) * ratio);
+                }
+                None if i > 0 => self.advance_recording_time(dt),
+                None => {}
+            }
+            figures.push(self.drawn_figure(view)?);
+        }
+        Ok(figures)
+    }
+
+    // 録画のために時間をdt秒進める
+    // 画面上と違い、処理落ちを考えずにシミュレーションのステップをすべて実行する
+    #[cfg(not(target_arch = "wasm32"))]
+    fn advance_recording_time(&mut self, dt: f64) {
+        self.advance_animation(dt);
+        if self.simulation.enabled {
+            self.simulation.accumulator += dt;
+            if self.simulation.accumulator >= self.simulation.timestep {
+                self.register_control_globals();
+            }
+            while self.simulation.accumulator >= self.simulation.timestep {
+                self.step_simulation();
+                self.simulation.accumulator -= self.simulation.timestep;
+            }
+        }
+        for slider in &mut self.sliders {
+            if slider.playback.playing {
+                slider.advance_playback(dt);
+            }
+        }
+    }
+
+    // 現在のコードとUI要素の値で別の実行環境を作り、画面上の状態を変えずにアニメーションを書き出す
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_animation(&self) -> Result<(), String> {
+        let settings = &self.record_settings;
+        let format = settings.format;
+        let title = self.metadata.title.trim();
+        let name = if title.is_empty() { "animation" } else { title };
+        let mut dialog = rfd::FileDialog::new()
+            .add_filter(format.label(), &[format.extension()])
+            .set_file_name(format!("{}.{}", name, format.extension()));
+        if let Some(dir) = self.document_path.as_deref().and_then(Path::parent) {
+            dialog = dialog.set_directory(dir);
+        }
+        let Some(path) = dialog.save_file() else {
+            return Ok(());
+        };
+
+        let mut recorder = ParametricPlotApp {
+            js_code: self.js_code.clone(),
+            pending_control_values: Some(self.control_values()),
+            plot_options: self.plot_options.clone(),
+            ..Default::default()
+        };
+        recorder.prepare_headless(&[])?;
+        let source = match &settings.sweep {
+            Some(name) => RecordSource::Sweep { name: name.clone(), from: settings.from, to: settings.to },
+            None => RecordSource::Time,
+        };
+        let recording = Recording { source, frames: settings.frames, fps: settings.fps };
+        let frames = recorder.record_animation(&recording, self.plot_bounds)?;
+        let ExportSettings { width, height, dpi } = self.export_settings;
+        export::write_animation(&frames, width, height, dpi, settings.fps, format, &path)
+    }
+
+    // アニメーションの書き出し設定の編集欄
+    #[cfg(not(target_arch = "wasm32"))]
+    fn show_record_settings(&mut self, ui: &mut Ui) {
+        let settings = &mut self.record_settings;
+        // スクリプトが変わってスライダがなくなった場合は時間に戻す
+        if settings.sweep.as_ref().is_some_and(|name| !self.sliders.iter().any(|s| &s.name == name)) {
+            settings.sweep = None;
+        }
+        egui::Grid::new("record_settings").num_columns(2).show(ui, |ui| {
+            ui.label("変化させるもの");
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt("record_source")
+                    .selected_text(settings.sweep.as_deref().unwrap_or("時間 (time)"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut settings.sweep, None, "時間 (time)");
+                        for slider in &self.sliders {
+                            if ui.selectable_label(settings.sweep.as_ref() == Some(&slider.name), &slider.name).clicked() {
+                                settings.sweep = Some(slider.name.clone());
+                                settings.from = slider.min;
+                                settings.to = slider.max;
+                            }
+                        }
+                    });
+                if settings.sweep.is_some() {
+                    ui.add(egui::DragValue::new(&mut settings.from).speed(0.01));
+                    ui.label("→");
+                    ui.add(egui::DragValue::new(&mut settings.to).speed(0.01));
+                }
+            });
+            ui.end_row();
+            ui.label("コマ数");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut settings.frames).range(1..=10000));
+                ui.add(egui::DragValue::new(&mut settings.fps).range(1.0..=120.0).speed(0.1).suffix(" fps"));
+                ui.weak(format!("{:.2} 秒", settings.frames as f64 / settings.fps));
+            });
+            ui.end_row();
+            ui.label("形式");
+            ui.horizontal(|ui| {
+                for format in [export::AnimationFormat::Gif, export::AnimationFormat::Apng, export::AnimationFormat::PngSequence] {
+                    ui.selectable_value(&mut settings.format, format, format.label());
+                }
+            });
+            ui.end_row();
+        });
+        if ui.button("アニメーションを書き出す").on_hover_text("画像の大きさと解像度は「書き出し」の設定を使う").clicked()
+            && let Err(e) = self.export_animation()
+        {
+            self.log_output.borrow_mut().push(LogEntry {
+                log_type: LogType::Stderr,
+                message: e,
+            });
+        }
+    }
+
+    // 現在の描画内容を、指定した表示範囲の図にする
+    #[cfg(not(target_arch = "wasm32"))]
+    fn figure(&self, view: Option<[f64; 4]>) -> export::Figure {
+        export::Figure {
+            scene: self.scene(),
+            view,
+            show_axes: self.plot_options.show_axes,
+            show_grid: self.plot_options.show_grid,
+            data_aspect: self.plot_options.data_aspect,
+        }
+    }
+
+    // 表示中の図をファイル選択ダイアログで選んだファイルに書き出す
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_figure(&self, format: ExportFormat) -> Result<(), String> {
+        let title = self.metadata.title.trim();
+        let name = if title.is_empty() { "graph" } else { title };
+        let extension = format.extension();
+        let mut dialog = rfd::FileDialog::new()
+            .add_filter(extension.to_ascii_uppercase(), &[extension])
+            .set_file_name(format!("{}.{}", name, extension));
+        if let Some(dir) = self.document_path.as_deref().and_then(Path::parent) {
+            dialog = dialog.set_directory(dir);
+        }
+        let Some(path) = dialog.save_file() else {
+            return Ok(());
+        };
+        let figure = self.figure(self.plot_bounds);
+        let ExportSettings { width, height, dpi } = self.export_settings;
+        let contents = match format {
+            ExportFormat::Svg => export::to_svg(&figure, width, height).into_bytes(),
+            ExportFormat::Png => export::to_png(&figure, width, height, dpi)?,
+            ExportFormat::Tex(flavor) => export::to_tex(&figure, width, height, flavor, true).into_bytes(),
+            ExportFormat::Csv => export::to_csv(&figure).into_bytes(),
+            ExportFormat::Json => export::to_data_json(&figure).into_bytes(),
+        };
+        std::fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // 書き出しの操作と画像の大きさ・解像度の設定欄。失敗した場合はログに記録する
+    #[cfg(not(target_arch = "wasm32"))]
+    fn show_export_actions(&mut self, ui: &mut Ui) {
+        ui.label("書き出し");
+        let mut result = Ok(());
+        if ui.button("SVG").on_hover_text("表示中の範囲をSVG画像に書き出す").clicked() {
+            result = self.export_figure(ExportFormat::Svg);
+        }
+        if ui.button("PNG").on_hover_text("表示中の範囲をPNG画像に書き出す").clicked() {
+            result = self.export_figure(ExportFormat::Png);
+        }
+        if ui.button("TikZ").on_hover_text("表示中の範囲をpdflatexでコンパイルできるTikZの図に書き出す").clicked() {
+            result = self.export_figure(ExportFormat::Tex(export::TexFlavor::Tikz));
+        }
+        if ui.button("PGFPlots").on_hover_text("表示中の範囲をpdflatexでコンパイルできるPGFPlotsの図に書き出す").clicked() {
+            result = self.export_figure(ExportFormat::Tex(export::TexFlavor::Pgfplots));
+        }
+        if ui.button("CSV").on_hover_text("曲線・ベクトル・多角形の点のデータをCSVに書き出す").clicked() {
+            result = self.export_figure(ExportFormat::Csv);
+        }
+        if ui.button("JSON").on_hover_text("曲線・ベクトル・多角形の点のデータをJSONに書き出す").clicked() {
+            result = self.export_figure(ExportFormat::Json);
+        }
+        let settings = &mut self.export_settings;
+        ui.add(egui::DragValue::new(&mut settings.width).range(1..=16384).suffix(" px"));
+        ui.label("×");
+        ui.add(egui::DragValue::new(&mut settings.height).range(1..=16384).suffix(" px"));
+        ui.add(egui::DragValue::new(&mut settings.dpi).range(24.0..=1200.0).speed(1.0).suffix(" dpi"));
+        if let Err(e) = result {
+            self.log_output.borrow_mut().push(LogEntry {
+                log_type: LogType::Stderr,
+                message: e,
+            });
+        }
+    }
+
+    // ドキュメントファイルに保存する
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_document(&mut self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_document().to_json()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.document_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    // ファイル選択ダイアログでドキュメントファイルを選ぶ
+    #[cfg(not(target_arch = "wasm32"))]
+    fn document_dialog(&self) -> rfd::FileDialog {
+        let mut dialog = rfd::FileDialog::new().add_filter("グラフ (*.graph.json)", &["json"]);
+        if let Some(dir) = self.document_path.as_deref().and_then(Path::parent) {
+            dialog = dialog.set_directory(dir);
+        }
+        dialog
+    }
+
+    // 開く・保存・名前を付けて保存の操作。失敗した場合はログに記録する
+    #[cfg(not(target_arch = "wasm32"))]
+    fn show_document_actions(&mut self, ui: &mut Ui) {
+        let mut result = Ok(());
+        if ui.button("開く").clicked()
+            && let Some(path) = self.document_dialog().pick_file()
+        {
+            result = self.open_document(&path);
+        }
+        let save_as = |app: &Self| {
+            let title = app.metadata.title.trim();
+            let name = if title.is_empty() { "untitled" } else { title };
+            app.document_dialog().set_file_name(format!("{}.{}", name, DOCUMENT_EXTENSION)).save_file()
+        };
+        if ui.button("保存").clicked() {
+            let path = self.document_path.clone().or_else(|| save_as(self));
+            if let Some(path) = path {
+                result = self.save_document(&path);
+            }
+        }
+        if ui.button("名前を付けて保存").clicked()
+            && let Some(path) = save_as(self)
+        {
+            result = self.save_document(&path);
+        }
+        if let Err(e) = result {
+            self.log_output.borrow_mut().push(LogEntry {
+                log_type: LogType::Stderr,
+                message: e,
+            });
+        }
+    }
+
+    // ドキュメントのメタデータとプロットの表示設定の編集欄
+    #[cfg(not(target_arch = "wasm32"))]
+    fn show_document_info(&mut self, ui: &mut Ui) {
+        egui::Grid::new("document_info").num_columns(2).show(ui, |ui| {
+            ui.label("タイトル");
+            ui.text_edit_singleline(&mut self.metadata.title);
+            ui.end_row();
+            ui.label("作成者");
+            ui.text_edit_singleline(&mut self.metadata.author);
+            ui.end_row();
+            ui.label("説明");
+            ui.text_edit_multiline(&mut self.metadata.description);
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.plot_options.show_axes, "軸を表示");
+            ui.checkbox(&mut self.plot_options.show_grid, "グリッドを表示");
+            let mut equal_aspect = self.plot_options.data_aspect.is_some();
+            if ui.checkbox(&mut equal_aspect, "縦横比を揃える").changed() {
+                self.plot_options.data_aspect = equal_aspect.then_some(1.0);
+            }
+        });
+    }
+
+    // スクリプトの実行環境を作り直してコードを読み込み、setup()でUI要素を定義する
+    // eframeに依存しないため、ウィンドウを開かずに描画する場合や埋め込み用のGraphPlotterでも使う
+    pub fn evaluate_script(&mut self) {
+        self.js_code_evaluated = false;
+        self.last_js_code = self.js_code.clone();
+
+        // 前回のスクリプトのグローバル変数や関数 (animate, onClick など) が残らないよう、実行環境を作り直す
+        self.engine.reset();
+
+        // Rust側stdout/stderrをスクリプトに提供
+        let log_output_stdout = self.log_output.clone();
+        self.register_api("stdout", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let msg = args.get_or_undefined(0).to_string();
+            println!("[JS stdout]: {}", msg); // Keep original console log
+            log_output_stdout.borrow_mut().push(LogEntry {
+                log_type: LogType::Stdout,
+                message: msg,
+            });
+            Ok(NativeValue::Undefined)
+        });
+
+        // stderr
+        let log_output_stderr = self.log_output.clone();
+        self.register_api("stderr", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            push_stderr(&log_output_stderr, args.get_or_undefined(0).to_string());
+            Ok(NativeValue::Undefined)
+        });
+
+        let layout_rc = Rc::new(RefCell::new(Vec::new()));
+        let presets_rc = Rc::new(RefCell::new(Vec::new()));
+        let sliders_rc = Rc::new(RefCell::new(Vec::new()));
+        let sliders_api = sliders_rc.clone();
+        let checkboxes_rc = Rc::new(RefCell::new(Vec::new()));
+        let checkboxes_api = checkboxes_rc.clone();
+        let color_pickers_rc = Rc::new(RefCell::new(Vec::new()));
+        let color_pickers_api = color_pickers_rc.clone();
+        let range_sliders_rc = Rc::new(RefCell::new(Vec::new()));
+        let selects_rc = Rc::new(RefCell::new(Vec::new()));
+        let text_inputs_rc = Rc::new(RefCell::new(Vec::new()));
+        let numbers_rc = Rc::new(RefCell::new(Vec::new()));
+        let buttons_rc = Rc::new(RefCell::new(Vec::new()));
+        let drag_points_rc = Rc::new(RefCell::new(Vec::new()));
+        let drag_points_api = drag_points_rc.clone();
+        let animations_rc = Rc::new(RefCell::new(Vec::new()));
+        let animations_api = animations_rc.clone();
+
+        // addPreset API
+        let presets_api = presets_rc.clone();
+        self.register_api("addPreset", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let values: BTreeMap<String, ControlValue> = serde_json::from_value(args.get_or_undefined(1).to_json())
+                .map_err(|e| format!("Invalid preset values: {}", e))?;
+            presets_api.borrow_mut().push(Preset { name, values, from_code: true });
+            Ok(NativeValue::Undefined)
+        });
+
+        // addSlider API
+        let layout_api = layout_rc.clone();
+        self.register_api("addSlider", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+
+            let min = params.get("min").as_f64().unwrap_or(0.0);
+            let max = params.get("max").as_f64().unwrap_or(1.0);
+            let step = params.get("step").as_f64().unwrap_or(0.001);
+            let default = params.get("default").as_f64().unwrap_or(0.0);
+            let scale = match params.get("scale") {
+                v if v.is_undefined() => SliderScale::Linear,
+                v => match v.to_string().as_str() {
+                    "linear" => SliderScale::Linear,
+                    "log" => SliderScale::Log,
+                    other => return Err(format!("Unknown slider scale: {}", other)),
+                },
+            };
+            check_bounds(min, max)?;
+            if scale == SliderScale::Log && !(min > 0.0 && max > min) {
+                return Err("scale: 'log' requires 0 < min < max".to_string());
+            }
+            let integer = params.get("integer").is_truthy();
+
+            // animate: true | 'once' | 'loop' | 'bounce' | { mode, speed }
+            // speedの既定値は、minからmaxまで5秒で動く速さ
+            let mut playback = SliderPlayback {
+                playing: false,
+                mode: PlayMode::Loop,
+                speed: (max - min).abs() / 5.0,
+                direction: 1.0,
+            };
+            let animate = params.get("animate");
+            if let Some(mode) = animate.as_str() {
+                playback.mode = PlayMode::parse(mode)
+                    .ok_or_else(|| format!("Unknown animate mode: {}", mode))?;
+                playback.playing = true;
+            } else if matches!(animate, NativeValue::Object(_)) {
+                let mode = animate.get("mode");
+                if !mode.is_undefined() {
+                    let mode = mode.to_string();
+                    playback.mode = PlayMode::parse(&mode)
+                        .ok_or_else(|| format!("Unknown animate mode: {}", mode))?;
+                }
+                if let Some(speed) = animate.get("speed").as_f64() {
+                    playback.speed = speed;
+                }
+                playback.playing = true;
+            } else {
+                playback.playing = animate.is_truthy();
+            }
+
+            let mut slider = SliderParam {
+                name,
+                min,
+                max,
+                step,
+                value: default,
+                scale,
+                integer,
+                playback,
+            };
+            // 既定値も刻み幅・範囲に合わせる
+            slider.value = slider.quantize(default);
+            sliders_api.borrow_mut().push(slider);
+            push_control_entry(&layout_api, ControlKind::Slider, sliders_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addRangeSlider API
+        let range_sliders_api = range_sliders_rc.clone();
+        let layout_api = layout_rc.clone();
+        self.register_api("addRangeSlider", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+            let min = params.get("min").as_f64().unwrap_or(0.0);
+            let max = params.get("max").as_f64().unwrap_or(1.0);
+            let step = params.get("step").as_f64().unwrap_or(0.001);
+            let default = params.get("default").as_point().unwrap_or([min, max]);
+            check_bounds(min, max)?;
+            let mut range = RangeSliderParam {
+                name,
+                min,
+                max,
+                step,
+                value: [min, max],
+            };
+            range.value = range.clamp_range(default);
+            range_sliders_api.borrow_mut().push(range);
+            push_control_entry(&layout_api, ControlKind::RangeSlider, range_sliders_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addCheckbox API
+        let layout_api = layout_rc.clone();
+        self.register_api("addCheckbox", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let label = args.get_or_undefined(1).to_string();
+            let params = args.get_or_undefined(2);
+
+            let default = params.get("default").as_bool().unwrap_or(true);
+
+            checkboxes_api.borrow_mut().push(CheckboxParam {
+                name,
+                label,
+                value: default,
+            });
+            push_control_entry(&layout_api, ControlKind::Checkbox, checkboxes_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addColorpicker API
+        let layout_api = layout_rc.clone();
+        self.register_api("addColorpicker", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+            let default_color_val = to_color(params.get("default"), Color::rgb(255, 255, 255)); // デフォルトは白
+            color_pickers_api.borrow_mut().push(ColorPickerParam {
+                name,
+                value: Color32::from(default_color_val),
+            });
+            push_control_entry(&layout_api, ControlKind::ColorPicker, color_pickers_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addSelect / addRadio API
+        // 両者は表示方法だけが異なるので、同じ処理をradioフラグ付きで登録する
+        for (func_name, radio) in [("addSelect", false), ("addRadio", true)] {
+            let selects_api = selects_rc.clone();
+            let layout_api = layout_rc.clone();
+            self.register_api(func_name, move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+                let name = args.get_or_undefined(0).to_string();
+                let options: Vec<String> = args.get_or_undefined(1).as_array()
+                    .ok_or_else(|| "Second argument must be an array of options".to_string())?
+                    .iter()
+                    .map(NativeValue::to_string)
+                    .collect();
+                let params = args.get_or_undefined(2);
+                let mut selected = 0;
+                let default = params.get("default");
+                if !default.is_undefined() {
+                    let default = default.to_string();
+                    selected = options.iter().position(|o| *o == default).unwrap_or(0);
+                }
+                selects_api.borrow_mut().push(SelectParam {
+                    name,
+                    options,
+                    selected,
+                    radio,
+                });
+                push_control_entry(&layout_api, ControlKind::Select, selects_api.borrow().len() - 1, params);
+                Ok(NativeValue::Undefined)
+            });
+        }
+
+        // addTextInput API
+        let text_inputs_api = text_inputs_rc.clone();
+        let layout_api = layout_rc.clone();
+        self.register_api("addTextInput", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+            let mut value = String::new();
+            let default = params.get("default");
+            if !default.is_undefined() {
+                value = default.to_string();
+            }
+            text_inputs_api.borrow_mut().push(TextInputParam {
+                name,
+                value,
+            });
+            push_control_entry(&layout_api, ControlKind::TextInput, text_inputs_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addNumber API
+        let numbers_api = numbers_rc.clone();
+        let layout_api = layout_rc.clone();
+        self.register_api("addNumber", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+            let value = params.get("default").as_f64().unwrap_or(0.0);
+            let step = params.get("step").as_f64().unwrap_or(0.1);
+            numbers_api.borrow_mut().push(NumberParam {
+                name,
+                step,
+                value,
+            });
+            push_control_entry(&layout_api, ControlKind::Number, numbers_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addButton API
+        let buttons_api = buttons_rc.clone();
+        let layout_api = layout_rc.clone();
+        self.register_api("addButton", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let label = args.get_or_undefined(0).to_string();
+            let callback = args.get_or_undefined(1).as_function()
+                .cloned()
+                .ok_or_else(|| "Second argument must be a function".to_string())?;
+            buttons_api.borrow_mut().push(ButtonParam {
+                label,
+                callback,
+            });
+            push_control_entry(&layout_api, ControlKind::Button, buttons_api.borrow().len() - 1, args.get_or_undefined(2));
+            Ok(NativeValue::Undefined)
+        });
+
+        // addDragPoint API
+        let layout_api = layout_rc.clone();
+        self.register_api("addDragPoint", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+            let default = params.get("default").as_point().unwrap_or([0.0, 0.0]);
+            let snap = params.get("snap").as_f64().unwrap_or(0.0);
+            let constrain = params.get("constrain").as_function().cloned();
+            let color = to_color(params.get("color"), Color::rgb(0, 120, 255));
+            drag_points_api.borrow_mut().push(DragPointParam {
+                name,
+                value: default,
+                snap: if snap.is_finite() { snap } else { 0.0 },
+                constrain,
+                color: Color32::from(color),
+            });
+            push_control_entry(&layout_api, ControlKind::DragPoint, drag_points_api.borrow().len() - 1, params);
+            Ok(NativeValue::Undefined)
+        });
+
+        // addAnimation API
+        self.register_api("addAnimation", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let params = args.get_or_undefined(1);
+            let min = params.get("min").as_f64().unwrap_or(0.0);
+            let max = params.get("max").as_f64().unwrap_or(1.0);
+            check_bounds(min, max)?;
+            let speed = params.get("speed").as_f64().unwrap_or(1.0);
+            let looping = match params.get("loop") {
+                v if v.is_undefined() => true,
+                v => v.is_truthy(),
+            };
+            animations_api.borrow_mut().push(AnimationParam {
+                name,
+                min,
+                max,
+                speed,
+                looping,
+                value: min,
+            });
+            Ok(NativeValue::Undefined)
+        });
+
+        // addParametricGraph API
+        let scene_api = self.scene.clone();
+        self.register_api("addParametricGraph", move |caller: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let f = args.get_or_undefined(1).as_function()
+                .ok_or_else(|| "Second argument must be a function".to_string())?;
+            let range = args.get_or_undefined(2);
+            let style = args.get_or_undefined(3);
+            let min = range.get("min").as_f64().unwrap_or(0.0);
+            let max = range.get("max").as_f64().unwrap_or(2.0 * std::f64::consts::PI);
+            let num_points = range.get("num_points").as_f64().unwrap_or(500.0);
+            // deltaは必ず計算する（rangeから取得しない）
+            let delta: f64 = (max - min) / num_points;
+            const DEFAULT_GRAPH_COLOR: Color = Color::rgb(200, 100, 0);
+            const DEFAULT_GRAPH_WEIGHT: f32 = 1.5;
+            let line_color = to_color(style.get("color"), DEFAULT_GRAPH_COLOR);
+            let line_weight = style.get("weight").as_f64().map_or(DEFAULT_GRAPH_WEIGHT, |w| w as f32);
+            let mut points = Vec::with_capacity(num_points as usize);
+            let mut params = Vec::with_capacity(num_points as usize);
+            let mut t = min;
+            // 最後の点まで確実に生成するためのループ
+            for _ in 0..=num_points as usize {
+                if let Some(point) = caller.call_function(f, &[t.into()]).ok().and_then(|result| result.as_point()) {
+                    points.push(point);
+                    params.push(t);
+                }
+                t += delta;
+            }
+            scene_api.borrow_mut().push(SceneItem::Curve {
+                name,
+                points,
+                params,
+                style: LineStyle { color: line_color, weight: line_weight },
+            });
+            Ok(NativeValue::Undefined)
+        });
+
+        // addVector API (api.md仕様)
+        let scene_api = self.scene.clone();
+        self.register_api("addVector", move |caller: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let start_func = args.get_or_undefined(1).as_function()
+                .ok_or_else(|| "Second argument must be a function".to_string())?;
+            let vec_func = args.get_or_undefined(2).as_function()
+                .ok_or_else(|| "Third argument must be a function".to_string())?;
+            let t = args.get_or_undefined(3).as_f64().unwrap_or(f64::NAN);
+            let style = args.get_or_undefined(4);
+            // デフォルト色・太さ
+            let color = to_color(style.get("color"), Color::rgb(0, 150, 200));
+            let weight = style.get("weight").as_f64().map_or(1.5, |w| w as f32);
+            // tで関数を呼び出し
+            let start = caller.call_function(start_func, &[t.into()]).ok().and_then(|result| result.as_point());
+            let vec = caller.call_function(vec_func, &[t.into()]).ok().and_then(|result| result.as_point());
+            if let (Some(start), Some(vec)) = (start, vec) {
+                scene_api.borrow_mut().push(SceneItem::Vector {
+                    name,
+                    origin: start,
+                    tip: [start[0] + vec[0], start[1] + vec[1]],
+                    t,
+                    style: LineStyle { color, weight },
+                });
+            }
+            Ok(NativeValue::Undefined)
+        });
+
+        // addPolygon API
+        let scene_api = self.scene.clone();
+        self.register_api("addPolygon", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let name = args.get_or_undefined(0).to_string();
+            let points_array = args.get_or_undefined(1).as_array()
+                .ok_or_else(|| "Expected array for points".to_string())?;
+            let style = args.get_or_undefined(2);
+
+            let points: Vec<[f64; 2]> = points_array.iter()
+                .filter_map(NativeValue::as_array)
+                .map(|point| {
+                    let coordinate = |i: usize| point.get(i).and_then(NativeValue::as_f64).unwrap_or(0.0);
+                    [coordinate(0), coordinate(1)]
+                })
+                .collect();
+
+            let default_color = Color::rgb(0, 0, 0);
+            let default_weight = 1.5;
+            let color = to_color(style.get("color"), default_color);
+            let weight = style.get("weight").as_f64().map_or(default_weight, |w| w as f32);
+
+            scene_api.borrow_mut().push(SceneItem::Polygon { name, points, style: PolygonStyle { stroke: color, weight } });
+            Ok(NativeValue::Undefined)
+        });
+
+        // addText API
+        let scene_api = self.scene.clone();
+        self.register_api("addText", move |_: &mut dyn ScriptCaller, args: &[NativeValue]| {
+            let text = args.get_or_undefined(0).to_string();
+            let position = args.get_or_undefined(1).as_point()
+                .ok_or_else(|| "Expected [x, y] for position".to_string())?;
+            let style = args.get_or_undefined(2);
+            let color = to_color(style.get("color"), Color::rgb(0, 0, 0));
+            let size = style.get("size").as_f64().map_or(14.0, |s| s as f32);
+            scene_api.borrow_mut().push(SceneItem::Text { text, position, style: TextStyle { color, size } });
+            Ok(NativeValue::Undefined)
+        });
+
+        // Rustで登録された関数
+        if let Err(e) = self.native.install(self.engine.as_mut()) {
+            log::error!("Native function error: {}", e);
+        }
+
+        // ログ出力をリセット
+        self.log_output.borrow_mut().clear();
+
+        // コードの読み込み
+        if let Err(e) = self.engine.load(self.js_code.as_str()) {
+            push_stderr(&self.log_output, format!("Load error: {}", e));
+        }
+
+        // Setup関数の実行
+        self.call_script("setup");
+
+        self.sliders = sliders_rc.borrow().clone();
+        self.checkboxes = checkboxes_rc.borrow().clone();
+        self.color_pickers = color_pickers_rc.borrow().clone();
+        self.range_sliders = range_sliders_rc.borrow().clone();
+        self.layout = layout_rc.borrow().clone();
+        // 画面から保存したプリセットはコードを変更しても残す (同名のものはスクリプトの定義を優先)
+        let mut presets: Vec<Preset> = presets_rc.borrow().clone();
+        for preset in self.presets.drain(..).filter(|p| !p.from_code) {
+            if !presets.iter().any(|p| p.name == preset.name) {
+                presets.push(preset);
+            }
+        }
+        self.presets = presets;
+        self.preset_transition = None;
+        self.selects = selects_rc.borrow().clone();
+        self.text_inputs = text_inputs_rc.borrow().clone();
+        self.numbers = numbers_rc.borrow().clone();
+        self.buttons = buttons_rc.borrow().clone();
+        self.drag_points = drag_points_rc.borrow().clone();
+        self.dragging_point = None;
+        self.animations = animations_rc.borrow().clone();
+        // ドラッグ点の初期値にもスナップと補正を適用する
+        for index in 0..self.drag_points.len() {
+            self.move_drag_point(index, self.drag_points[index].value);
+        }
+        // 履歴の復元などで指定された値を反映する
+        if let Some(values) = self.pending_control_values.take() {
+            self.apply_control_values(&values);
+        }
+        self.animate = self.engine.global("animate") == NativeValue::Bool(true);
+        self.animation = AnimationState::default();
+        // update関数があればシミュレーションとして扱い、init()で状態を初期化する
+        self.simulation = SimulationState {
+            enabled: self.engine.has_function("update"),
+            ..SimulationState::default()
+        };
+        self.reset_simulation();
+
+        self.js_code_evaluated = true;
+    }
+
+    // 評価するコードを置き換える (次にevaluate_scriptを呼ぶまで反映されない)
+    pub fn set_js_code(&mut self, code: String) {
+        self.js_code = code;
+        self.js_code_evaluated = false;
+    }
+
+    // スクリプトから呼び出せるRustの関数を登録する (次にevaluate_scriptを呼んだときから使える)
+    pub fn register_function<R: Into<NativeValue>>(&mut self, name: impl Into<String>, f: impl Fn(&[NativeValue]) -> Result<R, String> + 'static) {
+        self.native.register_function(name, f);
+        self.js_code_evaluated = false;
+    }
+
+    // 関数をまとめたオブジェクトを登録する (次にevaluate_scriptを呼んだときから使える)
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.native.register_module(module);
+        self.js_code_evaluated = false;
+    }
+
+    // スクリプトの実行環境を置き換える (次にevaluate_scriptを呼んだときから使う)
+    pub fn set_script_engine(&mut self, engine: Box<dyn ScriptEngine>) {
+        self.engine = engine;
+        self.js_code_evaluated = false;
+    }
+
+    // 描画APIの関数を実行環境に登録する
+    fn register_api(&mut self, name: &str, f: impl Fn(&mut dyn ScriptCaller, &[NativeValue]) -> Result<NativeValue, String> + 'static) {
+        let f: HostFn = Rc::new(f);
+        if let Err(e) = self.engine.register_function(name, f) {
+            log::error!("Register error ({}): {}", name, e);
+        }
+    }
+
+    // 最後にdraw()で作られた描画内容
+    pub fn scene(&self) -> Scene {
+        self.scene.borrow().clone()
+    }
+
+    // スクリプトの読み込み以降にstderrへ出力されたエラー
+    pub fn script_errors(&self) -> Vec<String> {
+        self.log_output.borrow().iter()
+            .filter(|entry| entry.log_type == LogType::Stderr)
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    // 現在のUI要素の値でdraw()を実行し、描画内容を作り直す
+    pub fn run_draw(&mut self) {
+        self.register_control_globals();
+        self.scene.borrow_mut().clear();
+        self.call_script("draw");
+    }
+
+    // setup()・draw()を呼び出す (未定義の場合を含め、エラーはstderrに出力する)
+    fn call_script(&mut self, name: &str) {
+        if let Err(e) = self.engine.call_global(name, &[]) {
+            push_stderr(&self.log_output, e);
+        }
+    }
+
+    // スクリプトのフック関数を呼び出す
+    // 関数が定義されていればtrueを返し、例外はstderrとしてログに記録する
+    fn call_js_hook(&mut self, name: &str, args: &[NativeValue]) -> bool {
+        if !self.engine.has_function(name) {
+            return false;
+        }
+        if let Err(e) = self.engine.call_global(name, args) {
+            self.log_output.borrow_mut().push(LogEntry {
+                log_type: LogType::Stderr,
+                message: format!("{}: {}", name, e),
+            });
+        }
+        true
+    }
+
+    // UI値をグローバル変数として注入する
+    fn register_control_globals(&mut self) {
+        for slider in &self.sliders {
+            self.engine.set_global(&slider.name, slider.value.into()).ok();
+        }
+        for checkbox in &self.checkboxes {
+            self.engine.set_global(&checkbox.name, checkbox.value.into()).ok();
+        }
+        for picker in &self.color_pickers {
+            let rgb = vec![picker.value.r() as f64, picker.value.g() as f64, picker.value.b() as f64];
+            self.engine.set_global(&picker.name, rgb.into()).ok();
+        }
+        if self.is_animated() {
+            self.engine.set_global("time", self.animation.time.into()).ok();
+            self.engine.set_global("frame", (self.animation.frame as f64).into()).ok();
+            for anim in &self.animations {
+                self.engine.set_global(&anim.name, anim.value.into()).ok();
+            }
+        }
+        for range in &self.range_sliders {
+            self.engine.set_global(&range.name, range.value.into()).ok();
+        }
+        for select in &self.selects {
+            let value = select.options.get(select.selected).cloned().unwrap_or_default();
+            self.engine.set_global(&select.name, value.into()).ok();
+        }
+        for input in &self.text_inputs {
+            self.engine.set_global(&input.name, input.value.clone().into()).ok();
+        }
+        for number in &self.numbers {
+            self.engine.set_global(&number.name, number.value.into()).ok();
+        }
+        for point in &self.drag_points {
+            self.engine.set_global(&point.name, point.value.into()).ok();
+        }
+    }
+
+    // 現在のUI要素の値を名前ごとに取得する
+    pub fn control_values(&self) -> BTreeMap<String, ControlValue> {
+        let mut values = BTreeMap::new();
+        for slider in &self.sliders {
+            values.insert(slider.name.clone(), ControlValue::Number(slider.value));
+        }
+        for range in &self.range_sliders {
+            values.insert(range.name.clone(), ControlValue::Pair(range.value));
+        }
+        for checkbox in &self.checkboxes {
+            values.insert(checkbox.name.clone(), ControlValue::Bool(checkbox.value));
+        }
+        for picker in &self.color_pickers {
+            values.insert(picker.name.clone(), ControlValue::Color([picker.value.r(), picker.value.g(), picker.value.b()]));
+        }
+        for select in &self.selects {
+            let option = select.options.get(select.selected).cloned().unwrap_or_default();
+            values.insert(select.name.clone(), ControlValue::Text(option));
+        }
+        for input in &self.text_inputs {
+            values.insert(input.name.clone(), ControlValue::Text(input.value.clone()));
+        }
+        for number in &self.numbers {
+            values.insert(number.name.clone(), ControlValue::Number(number.value));
+        }
+        for point in &self.drag_points {
+            values.insert(point.name.clone(), ControlValue::Pair(point.value));
+        }
+        values
+    }
+
+    // 名前ごとの値をUI要素に反映する (名前や型が一致しない値は無視する)
+    pub fn apply_control_values(&mut self, values: &BTreeMap<String, ControlValue>) {
+        for slider in &mut self.sliders {
+            if let Some(ControlValue::Number(v)) = values.get(&slider.name) {
+                slider.value = slider.quantize(*v);
+            }
+        }
+        for range in &mut self.range_sliders {
+            if let Some(ControlValue::Pair([lo, hi])) = values.get(&range.name) {
+                range.value = range.clamp_range([*lo, *hi]);
+            }
+        }
+        for checkbox in &mut self.checkboxes {
+            if let Some(ControlValue::Bool(v)) = values.get(&checkbox.name) {
+                checkbox.value = *v;
+            }
+        }
+        for picker in &mut self.color_pickers {
+            if let Some(ControlValue::Color([r, g, b])) = values.get(&picker.name) {
+                picker.value = Color32::from_rgb(*r, *g, *b);
+            }
+        }
+        for select in &mut self.selects {
+            if let Some(ControlValue::Text(option)) = values.get(&select.name)
+                && let Some(index) = select.options.iter().position(|o| o == option)
+            {
+                select.selected = index;
+            }
+        }
+        for input in &mut self.text_inputs {
+            if let Some(ControlValue::Text(v)) = values.get(&input.name) {
+                input.value = v.clone();
+            }
+        }
+        for number in &mut self.numbers {
+            if let Some(ControlValue::Number(v)) = values.get(&number.name) {
+                number.value = *v;
+            }
+        }
+        // ドラッグ点はドラッグしたときと同じくスナップと補正を通す
+        for index in 0..self.drag_points.len() {
+            if let Some(ControlValue::Pair(v)) = values.get(&self.drag_points[index].name) {
+                self.move_drag_point(index, *v);
+            }
+        }
+    }
+
+    // プリセットを適用する (animate_presetsが有効なら補間しながら切り替える)
+    fn apply_preset(&mut self, name: &str) {
+        let Some(preset) = self.presets.iter().find(|p| p.name == name) else {
+            return;
+        };
+        let to = preset.values.clone();
+        self.selected_preset = Some(name.to_string());
+        if self.animate_presets {
+            self.preset_transition = Some(PresetTransition {
+                from: self.control_values(),
+                to,
+                progress: 0.0,
+            });
+        } else {
+            self.preset_transition = None;
+            self.apply_control_values(&to);
+        }
+    }
+
+    // 現在の値をプリセットとして保存する (同名のプリセットは上書きする)
+    fn save_preset(&mut self, name: &str) {
+        let values = self.control_values();
+        match self.presets.iter_mut().find(|p| p.name == name) {
+            Some(preset) => {
+                preset.values = values;
+                preset.from_code = false;
+            }
+            None => self.presets.push(Preset { name: name.to_string(), values, from_code: false }),
+        }
+        self.selected_preset = Some(name.to_string());
+    }
+
+    // プリセット切り替えのアニメーションをdt秒進める。値が変化したらtrue
+    fn advance_preset_transition(&mut self, dt: f64) -> bool {
+        let Some(transition) = &mut self.preset_transition else {
+            return false;
+        };
+        transition.progress = (transition.progress + dt / PRESET_TRANSITION_SECONDS).min(1.0);
+        // smoothstepで始めと終わりを緩やかにする
+        let t = transition.progress * transition.progress * (3.0 - 2.0 * transition.progress);
+        let values: BTreeMap<String, ControlValue> = transition.to.iter()
+            .map(|(name, to)| {
+                let value = match transition.from.get(name) {
+                    Some(from) => from.lerp(to, t),
+                    None => to.clone(),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        if transition.progress >= 1.0 {
+            self.preset_transition = None;
+        }
+        self.apply_control_values(&values);
+        true
+    }
+
+    // パラメータウィンドウのプリセット欄
+    // プリセットが適用されて値が変わった場合はtrue
+    fn show_presets(&mut self, ui: &mut Ui) -> bool {
+        let mut applied = None;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("プリセット");
+            let selected_text = self.selected_preset.clone().unwrap_or_else(|| "(未選択)".to_string());
+            egui::ComboBox::from_id_salt("presets")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    for preset in &self.presets {
+                        let selected = self.selected_preset.as_deref() == Some(preset.name.as_str());
+                        if ui.selectable_label(selected, &preset.name).clicked() {
+                            applied = Some(preset.name.clone());
+                        }
+                    }
+                });
+            ui.checkbox(&mut self.animate_presets, "補間").on_hover_text("値をなめらかに切り替える");
+        });
+        ui.horizontal(|ui| {
+            ui.add(TextEdit::singleline(&mut self.new_preset_name).hint_text("プリセット名").desired_width(120.0));
+            let name = self.new_preset_name.trim().to_string();
+            if ui.add_enabled(!name.is_empty(), egui::Button::new("保存")).clicked() {
+                self.save_preset(&name);
+                self.new_preset_name.clear();
+            }
+            let selected = self.selected_preset.clone();
+            if ui.add_enabled(selected.is_some(), egui::Button::new("削除")).clicked() {
+                self.presets.retain(|p| Some(&p.name) != selected.as_ref());
+                self.selected_preset = None;
+            }
+        });
+        if let Some(name) = applied {
+            self.apply_preset(&name);
+            changed = self.preset_transition.is_none();
+        }
+        changed
+    }
+
+    fn history_state(&self) -> HistoryState {
+        HistoryState { code: self.js_code.clone(), values: self.control_values() }
+    }
+
+    // 現在の状態が履歴と異なれば記録する
+    // ドラッグ中やプリセット切り替え中は、操作が終わってから1つの履歴として記録する
+    fn record_history(&mut self, ctx: &egui::Context) {
+        if !self.js_code_evaluated || self.pending_control_values.is_some() {
+            return;
+        }
+        let busy = ctx.input(|i| i.pointer.any_down()) || self.preset_transition.is_some();
+        if busy {
+            return;
+        }
+        self.record_state(ctx.input(|i| i.time));
+    }
+
+    // 現在の状態を時刻timeの変更として記録する
+    // 自動再生中のスライダの値の変化は、再生が止まってから記録する
+    fn record_state(&mut self, time: f64) {
+        let mut state = self.history_state();
+        if let Some(current) = self.history.current_state() {
+            for slider in self.sliders.iter().filter(|s| s.playback.playing) {
+                if let Some(value) = current.values.get(&slider.name) {
+                    state.values.insert(slider.name.clone(), value.clone());
+                }
+            }
+        }
+        let label = match self.history.current_state() {
+            Some(current) if *current == state => return,
+            Some(current) => history_label(current, &state),
+            None => "初期状態".to_string(),
+        };
+        self.history.record(label, state, time);
+    }
+
+    // 履歴のindex番目の状態に戻す
+    fn restore_history(&mut self, index: usize) {
+        let Some(state) = self.history.go_to(index).cloned() else {
+            return;
+        };
+        self.preset_transition = None;
+        if state.code != self.js_code {
+            // コードを戻した場合は再評価の後で値を反映する
+            self.js_code = state.code;
+            #[cfg(target_arch = "wasm32")]
+            update_monaco(&self.js_code);
+            self.pending_control_values = Some(state.values);
+        } else {
+            self.apply_control_values(&state.values);
+            self.redraw_requested = true;
+        }
+    }
+
+    fn undo(&mut self) {
+        if self.history.can_undo() {
+            self.restore_history(self.history.current - 1);
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.history.can_redo() {
+            self.restore_history(self.history.current + 1);
+        }
+    }
 
-#[cfg(not(target_arch = "wasm32"))]
-use colored::Colorize;
+    // 履歴ウィンドウの中身
+    fn show_history(&mut self, ui: &mut Ui) {
+        let mut jump = None;
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.history.can_undo(), egui::Button::new("元に戻す")).on_hover_text("Ctrl+Z").clicked() {
+                jump = Some(self.history.current - 1);
+            }
+            if ui.add_enabled(self.history.can_redo(), egui::Button::new("やり直し")).on_hover_text("Ctrl+Shift+Z").clicked() {
+                jump = Some(self.history.current + 1);
+            }
+            // Web版ではエディタがHTML側にあるため、新規ドキュメントの操作をここに置く
+            #[cfg(target_arch = "wasm32")]
+            if ui.button("新規").on_hover_text("既定のスクリプトで新しく始める").clicked() {
+                self.new_document();
+            }
+        });
+        ui.separator();
+        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            for (i, entry) in self.history.entries.iter().enumerate().rev() {
+                let mut text = egui::RichText::new(&entry.label);
+                if i > self.history.current {
+                    text = text.weak(); // やり直しで戻れる項目
+                }
+                if ui.selectable_label(i == self.history.current, text).clicked() {
+                    jump = Some(i);
+                }
+            }
+        });
+        if let Some(index) = jump {
+            self.restore_history(index);
+        }
+    }
 
-// WASMでのログ出力設定
-#[cfg(target_arch = "wasm32")]
-pub fn setup_logging() {
-    console_log::init_with_level(log::Level::Debug).expect("error initializing log");
-}
+    // 共有リンクに含める現在の状態
+    fn shared_state(&self) -> SharedState {
+        SharedState {
+            code: Some(self.js_code.clone()),
+            values: Some(self.control_values()),
+            view: self.plot_bounds,
+            preset: self.selected_preset.clone(),
+            presets: self.presets.iter().filter(|p| !p.from_code).cloned().collect(),
+        }
+    }
 
-// ネイティブでのログ出力設定
-#[cfg(not(target_arch = "wasm32"))]
-pub fn setup_logging() {
-    env_logger::init();
-}
+    // 共有リンクから読み込んだ状態を反映する
+    fn apply_shared_state(&mut self, state: SharedState) {
+        self.preset_transition = None;
+        // リンクのプリセットを追加する (同名のものは置き換える)
+        for preset in state.presets {
+            self.presets.retain(|p| p.name != preset.name);
+            self.presets.push(preset);
+        }
+        if state.preset.is_some() {
+            self.selected_preset = state.preset;
+        }
+        if state.view.is_some() {
+            self.pending_plot_bounds = state.view;
+        }
+        match state.code {
+            // コードが変わる場合は再評価の後で値を反映する
+            Some(code) if code != self.js_code => {
+                self.js_code = code;
+                self.pending_control_values = state.values;
+            }
+            _ => {
+                if let Some(values) = state.values {
+                    self.apply_control_values(&values);
+                    self.redraw_requested = true;
+                }
+            }
+        }
+    }
 
-use std::cell::RefCell;
-use std::rc::Rc;
+    // 現在の状態 (コード・UI要素の値・表示範囲・プリセット) をURLのクエリに書き込む
+    #[cfg(target_arch = "wasm32")]
+    fn sync_url(&mut self, ctx: &egui::Context) {
+        if !self.js_code_evaluated || ctx.input(|i| i.pointer.any_down()) {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+        if now - self.last_url_update < URL_UPDATE_INTERVAL {
+            return;
+        }
+        let Some(window) = web_sys::window() else {
+            return;
+        };
+        let Ok(url) = window.location().href().and_then(|href| web_sys::Url::new(&href)) else {
+            return;
+        };
+        let query = self.shared_state().to_query(&url.search());
+        if query == self.last_shared_query {
+            return;
+        }
+        url.set_search(&format!("?{}", query));
+        let _ = window.history().expect("history").replace_state_with_url(&wasm_bindgen::JsValue::NULL, "", Some(&url.href()));
+        self.last_shared_query = query;
+        self.last_url_update = now;
+    }
 
-// スライダ情報を保持する構造体
-#[derive(Clone)]
-pub struct SliderParam {
-    name: String,
-    min: f64,
-    max: f64,
-    step: f64,
-    value: f64,
-}
+    // パラメータウィンドウにUI要素を1つ表示する
+    // ボタンはクリック、それ以外は値の変更をResponseで返す
+    fn show_control(&mut self, ui: &mut Ui, entry: &ControlEntry) -> Response {
+        let i = entry.index;
+        let response = match entry.kind {
+            ControlKind::Slider => {
+                let slider = &mut self.sliders[i];
+                let label = entry.label.clone().unwrap_or_else(|| slider.name.clone());
+                ui.add(CustomSlider::new(slider).label(label))
+            }
+            ControlKind::RangeSlider => {
+                let range = &mut self.range_sliders[i];
+                let label = entry.label.clone().unwrap_or_else(|| range.name.clone());
+                ui.add(CustomRangeSlider::new(range).label(label))
+            }
+            ControlKind::Checkbox => {
+                let checkbox = &mut self.checkboxes[i];
+                let label = entry.label.as_ref().unwrap_or(&checkbox.label);
+                ui.checkbox(&mut checkbox.value, label.as_str())
+            }
+            ControlKind::ColorPicker => {
+                let picker = &mut self.color_pickers[i];
+                ui.horizontal(|ui| {
+                    ui.label(entry.label.as_ref().unwrap_or(&picker.name));
+                    // Color32 (u8 0-255 per channel) を [f32; 3] (0.0-1.0 per channel) に変換
+                    let mut color_f32 = [
+                        picker.value.r() as f32 / 255.0,
+                        picker.value.g() as f32 / 255.0,
+                        picker.value.b() as f32 / 255.0,
+                    ];
+                    let response = ui.color_edit_button_rgb(&mut color_f32);
+                    if response.changed() {
+                        // [f32; 3] から Color32 に戻す (アルファは常に255)
+                        picker.value = Color32::from_rgb((color_f32[0] * 255.0) as u8, (color_f32[1] * 255.0) as u8, (color_f32[2] * 255.0) as u8);
+                    }
+                    response
+                }).inner
+            }
+            ControlKind::Select => {
+                let select = &mut self.selects[i];
+                let label = entry.label.as_ref().unwrap_or(&select.name);
+                if select.radio {
+                    ui.vertical(|ui| {
+                        ui.label(label);
+                        ui.horizontal_wrapped(|ui| {
+                            let mut response = ui.allocate_response(egui::Vec2::ZERO, Sense::hover());
+                            for (j, option) in select.options.iter().enumerate() {
+                                response |= ui.radio_value(&mut select.selected, j, option);
+                            }
+                            response
+                        }).inner
+                    }).inner
+                } else {
+                    ui.horizontal(|ui| {
+                        ui.label(label);
+                        let selected_text = select.options.get(select.selected).cloned().unwrap_or_default();
+                        let mut changed = false;
+                        let mut response = egui::ComboBox::from_id_salt(("select", &select.name))
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                for (j, option) in select.options.iter().enumerate() {
+                                    changed |= ui.selectable_value(&mut select.selected, j, option).changed();
+                                }
+                            })
+                            .response;
+                        if changed {
+                            response.mark_changed();
+                        }
+                        response
+                    }).inner
+                }
+            }
+            ControlKind::TextInput => {
+                let input = &mut self.text_inputs[i];
+                ui.horizontal(|ui| {
+                    ui.label(entry.label.as_ref().unwrap_or(&input.name));
+                    ui.text_edit_singleline(&mut input.value)
+                }).inner
+            }
+            ControlKind::Number => {
+                let number = &mut self.numbers[i];
+                ui.horizontal(|ui| {
+                    ui.label(entry.label.as_ref().unwrap_or(&number.name));
+                    ui.add(egui::DragValue::new(&mut number.value).speed(number.step))
+                }).inner
+            }
+            ControlKind::Button => {
+                let button = &self.buttons[i];
+                ui.button(entry.label.as_ref().unwrap_or(&button.label))
+            }
+            ControlKind::DragPoint => {
+                let point = &self.drag_points[i];
+                let label = entry.label.as_ref().unwrap_or(&point.name);
+                ui.label(format!("{}: ({:.3}, {:.3})", label, point.value[0], point.value[1]))
+            }
+        };
+        match &entry.description {
+            Some(description) => response.on_hover_text(description),
+            None => response,
+        }
+    }
 
-// チェックボックス情報を保持する構造体
-#[derive(Clone)]
-struct CheckboxParam {
-    name: String,   // JSで参照する変数名
-    label: String,  // チェックボックス横のラベル
-    value: bool,    // 現在値
-}
+    // パラメータウィンドウに表示する項目があるか
+    fn has_parameter_panel(&self) -> bool {
+        !self.sliders.is_empty()
+            || !self.range_sliders.is_empty()
+            || !self.checkboxes.is_empty()
+            || !self.color_pickers.is_empty()
+            || !self.selects.is_empty()
+            || !self.text_inputs.is_empty()
+            || !self.numbers.is_empty()
+            || !self.buttons.is_empty()
+            || !self.drag_points.is_empty()
+            || self.is_animated()
+            || self.simulation.enabled
+    }
 
-// カラーピッカー情報を保持する構造体
-#[derive(Clone)]
-struct ColorPickerParam {
-    name: String,   // JSで参照する変数名
-    value: Color32, // 現在の色 (egui::Color32)
-}
+    // シミュレーションを初期状態に戻す (init()を再実行する)
+    fn reset_simulation(&mut self) {
+        self.simulation.accumulator = 0.0;
+        self.simulation.steps = 0;
+        self.register_control_globals();
+        self.call_js_hook("init", &[]);
+    }
 
-// ログメッセージの種類と内容
-#[derive(Clone, Debug, PartialEq)] // PartialEqを追加して比較できるようにする
-enum LogType {
-    Stdout,
-    Stderr,
-}
+    // シミュレーションを1ステップ進める (update(dt)を呼び出す)
+    fn step_simulation(&mut self) {
+        let dt = self.simulation.timestep;
+        self.call_js_hook("update", &[dt.into()]);
+        self.simulation.steps += 1;
+    }
 
-#[derive(Clone, Debug, PartialEq)] // PartialEqを追加
-struct LogEntry {
-    log_type: LogType,
-    message: String,
-}
+    // 経過時間elapsedを固定タイムステップで消化する。1ステップ以上進んだらtrue
+    fn advance_simulation(&mut self, elapsed: f64) -> bool {
+        self.simulation.accumulator += elapsed;
+        let mut stepped = 0;
+        if self.simulation.accumulator >= self.simulation.timestep {
+            self.register_control_globals();
+        }
+        while self.simulation.accumulator >= self.simulation.timestep {
+            if stepped >= MAX_SIMULATION_STEPS_PER_FRAME {
+                // 追いつけない分は捨てる
+                self.simulation.accumulator = 0.0;
+                break;
+            }
+            self.step_simulation();
+            self.simulation.accumulator -= self.simulation.timestep;
+            stepped += 1;
+        }
+        stepped > 0
+    }
 
-// アプリケーションの状態を保持する構造体
-pub struct ParametricPlotApp {
-    sliders: Vec<SliderParam>,
-    checkboxes: Vec<CheckboxParam>, // チェックボックス一覧を追加
-    color_pickers: Vec<ColorPickerParam>, // カラーピッカー一覧を追加
-    js_context: BoaContext,
-    js_code_evaluated: bool,
-    graph_lines: Rc<RefCell<Vec<(String, Vec<[f64; 2]>, Color32, f32)>>>, // (名前, 点群, 色, 太さ)
-    vectors: Rc<RefCell<Vec<(String, Vec<[f64; 2]>, Vec<[f64; 2]>, Color32, f32)>>>,  // (名前, 始点群, 終点群, 色, 太さ)
-    js_code: String, // JavaScriptエディタ用
-    last_js_code: String, // 前回実行したJSコード
-    api_docs_content: String,
-    log_output: Rc<RefCell<Vec<LogEntry>>>,
-    commonmark_cache: egui_commonmark::CommonMarkCache,
-    polygons: Rc<RefCell<Vec<(String, Vec<[f64; 2]>, Color32, Color32, f32)>>>, // (名前, 頂点群, 枠線色, 塗りつぶし色, 線の太さ)
-}
+    // アニメーションモードかどうか
+    fn is_animated(&self) -> bool {
+        self.animate || !self.animations.is_empty()
+    }
 
-impl Default for ParametricPlotApp {
-    fn default() -> Self {
-        let js_context = BoaContext::default();
-        let mut default_js_code = r#"
-function setup() {
-    addSlider('radius', { min: 0.5, max: 5.0, step: 0.001, default: 1.0 });
-    addColorpicker('lineColor', { default: [255, 0, 0] });
-    addCheckbox('show', '図形を表示する', { default: true });
-}
+    // 1フレーム (dt秒) 分、アニメーション・シミュレーション・プリセットの切り替え・スライダの自動再生を進める
+    // 描画内容が変わりうる場合はtrueを返す
+    pub fn advance_frame(&mut self, dt: f64) -> bool {
+        let mut changed = false;
+        if self.is_animated() && self.animation.playing {
+            self.advance_animation(dt * self.animation.speed);
+            changed = true;
+        }
+        if self.simulation.enabled && self.simulation.running && self.advance_simulation(dt) {
+            changed = true;
+        }
+        if self.advance_preset_transition(dt) {
+            changed = true;
+        }
+        for slider in &mut self.sliders {
+            if slider.playback.playing {
+                slider.advance_playback(dt);
+                changed = true;
+            }
+        }
+        changed
+    }
 
-function draw() {
-    if (show) {
-        // 円の描画
-        addParametricGraph(
-            '円',
-            function(t) { return [radius * Math.cos(t), radius * Math.sin(t)]; },
-            { min: 0, max: 2 * Math.PI, num_points: 100 },
-            { color: lineColor, weight: 2.0 }
-        );
+    // アニメーションをdt秒進める
+    fn advance_animation(&mut self, dt: f64) {
+        self.animation.time += dt;
+        self.animation.frame += 1;
+        for anim in &mut self.animations {
+            anim.advance(dt);
+        }
+    }
 
-        // 三角形の描画
-        addPolygon(
-            '三角形',
-            [[radius, 0], [radius * Math.cos(2*Math.PI/3), radius * Math.sin(2*Math.PI/3)], [radius * Math.cos(4*Math.PI/3), radius * Math.sin(4*Math.PI/3)]],
-            { color: lineColor, weight: 2.0 }
-        );
+    // スクリーン座標posにあるドラッグ点のインデックスを返す (重なっている場合は手前のもの)
+    fn drag_point_at(&self, transform: &PlotTransform, pos: Pos2) -> Option<usize> {
+        const HANDLE_HIT_RADIUS: f32 = 10.0;
+        self.drag_points.iter().rposition(|point| {
+            let screen = transform.position_from_point(&PlotPoint::new(point.value[0], point.value[1]));
+            screen.distance(pos) <= HANDLE_HIT_RADIUS
+        })
     }
-}
-"#.to_string();
-        #[cfg(target_arch = "wasm32")]
-        {
-            use web_sys::Url;
-            if let Some(window) = web_sys::window() {
-                if let Ok(href) = window.location().href() {
-                    if let Ok(mut url) = Url::new(&href) {
-                        // クエリをパース
-                        let search = url.search();
-                        let mut code_val = None;
-                        if !search.is_empty() {
-                            for (k, v) in parse(search.trim_start_matches('?').as_bytes()) {
-                                if k == "code" {
-                                    // base64デコード→utf8
-                                    if let Ok(decoded_b64) = general_purpose::URL_SAFE_NO_PAD.decode(&*v) {
-                                        if let Ok(decoded_str) = String::from_utf8(decoded_b64) {
-                                            code_val = Some(decoded_str);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if let Some(code) = code_val {
-                            if !code.trim().is_empty() {
-                                default_js_code = code;
-                            }
-                        }
+
+    // ドラッグ点をtargetへ移動する (snap・constrainを適用)
+    // 座標が変化した場合はtrueを返す
+    fn move_drag_point(&mut self, index: usize, target: [f64; 2]) -> bool {
+        let (snap, constrain) = {
+            let point = &self.drag_points[index];
+            (point.snap, point.constrain.clone())
+        };
+        let mut value = target;
+        if snap > 0.0 {
+            value = value.map(|v| (v / snap).round() * snap);
+        }
+        if let Some(constrain) = constrain {
+            match self.engine.call_function(&constrain, &[value[0].into(), value[1].into()]) {
+                Ok(result) => {
+                    if let Some(constrained) = result.as_point() {
+                        value = constrained;
                     }
                 }
+                Err(e) => {
+                    self.log_output.borrow_mut().push(LogEntry {
+                        log_type: LogType::Stderr,
+                        message: format!("constrain ({}): {}", self.drag_points[index].name, e),
+                    });
+                }
             }
-            update_monaco(&default_js_code);
         }
-        Self {
-            sliders: Vec::new(),
-            checkboxes: Vec::new(),
-            color_pickers: Vec::new(),
-            js_context,
-            js_code_evaluated: false,
-            graph_lines: Rc::new(RefCell::new(Vec::new())),
-            vectors: Rc::new(RefCell::new(Vec::new())),
-            js_code: default_js_code.clone(),
-            last_js_code: default_js_code,
-            api_docs_content: include_str!("../doc/api.md").to_string(),
-            log_output: Rc::new(RefCell::new(Vec::new())),
-            commonmark_cache: egui_commonmark::CommonMarkCache::default(),
-            polygons: Rc::new(RefCell::new(Vec::new())),
+        if self.drag_points[index].value == value {
+            return false;
         }
+        self.drag_points[index].value = value;
+        true
     }
 }
 
-impl ParametricPlotApp {}
-
 impl App for ParametricPlotApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let session = SavedSession {
+            document: self.to_document(),
+            #[cfg(not(target_arch = "wasm32"))]
+            path: self.document_path.clone(),
+        };
+        if let Ok(json) = serde_json::to_string(&session) {
+            storage.set_string(SESSION_STORAGE_KEY, json);
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
         let mut js_code_changed = false;
 
@@ -180,6 +2733,10 @@ impl App for ParametricPlotApp {
         if let Ok(mut content) = PENDING_CONTENT.try_lock() {
             match content.clone() {
                 Some(x) => {
+                    // 内容の変わらない再実行 (エディタへの反映が戻ってきた場合を含む) ではUI要素の値を保つ
+                    if x == self.js_code && self.js_code_evaluated && self.pending_control_values.is_none() {
+                        self.pending_control_values = Some(self.control_values());
+                    }
                     self.js_code = x;
                     js_code_changed = true;
                     *content = None;
@@ -187,9 +2744,53 @@ impl App for ParametricPlotApp {
                 _ => {}
             }
         }
+        // ブラウザの戻る・進むで移動した先のURLの状態を読み込む
+        #[cfg(target_arch = "wasm32")]
+        if let Some(query) = PENDING_QUERY.try_lock().ok().and_then(|mut query| query.take()) {
+            match SharedState::from_query(&query) {
+                Ok(state) => {
+                    let code_changed = state.code.as_ref().is_some_and(|code| *code != self.js_code);
+                    self.apply_shared_state(state);
+                    if code_changed {
+                        update_monaco(&self.js_code);
+                    }
+                }
+                Err(e) => web_sys::console::error_1(&e.into()),
+            }
+        }
+
+        // --- 監視しているスクリプトファイルの読み込み ---
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.poll_script_watch(ctx.input(|i| i.time));
+            if let Some(error) = self.script_watch.as_ref().and_then(|w| w.error.as_ref()) {
+                egui::TopBottomPanel::top("watch_error").show(ctx, |ui| {
+                    ui.colored_label(Color32::RED, format!("監視中のファイルを読み込めません: {}", error));
+                });
+            }
+        }
+
+        // --- 元に戻す・やり直し ---
+        // テキスト入力中はテキスト欄自身の取り消し操作を優先する
+        let editing_text = ctx.memory(|m| m.focused()).is_some_and(|id| TextEdit::load_state(ctx, id).is_some());
+        if !editing_text {
+            let redo = ctx.input_mut(|i| i.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)));
+            let undo = ctx.input_mut(|i| i.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z)));
+            if redo {
+                self.redo();
+            } else if undo {
+                self.undo();
+            }
+        }
+        let _subwin = egui::Window::new("履歴")
+            .default_open(false)
+            .resizable(true)
+            .show(ctx, |ui| self.show_history(ui));
+        #[cfg(target_arch = "wasm32")]
+        _subwin.aware();
 
         // API Documentationウィンドウを常に表示
-        let subwin = egui::Window::new("API Documentation")
+        let _subwin = egui::Window::new("API Documentation")
             .default_size([700.0, 500.0])
             .resizable(true)
             .show(ctx, |ui| {
@@ -204,14 +2805,47 @@ impl App for ParametricPlotApp {
                 });
             });
         #[cfg(target_arch = "wasm32")]
-        subwin.aware();
+        _subwin.aware();
 
         #[cfg(not(target_arch = "wasm32"))]
-        let subwin = egui::Window::new("Javascript Editor").min_width(600.0).show(ctx, |ui| {
-            let mut theme = syntax_highlighting::CodeTheme::from_memory(ui.ctx(), ui.style());
-            if ui.button("再実行").clicked() {
-                js_code_changed = true;
-            }
+        egui::Window::new("Javascript Editor").min_width(600.0).show(ctx, |ui| {
+            let theme = syntax_highlighting::CodeTheme::from_memory(ui.ctx(), ui.style());
+            ui.horizontal(|ui| {
+                if ui.button("新規").on_hover_text("既定のスクリプトで新しく始める").clicked() {
+                    self.new_document();
+                }
+                self.show_document_actions(ui);
+                if ui.button("再実行").clicked() {
+                    js_code_changed = true;
+                }
+                if let Some(path) = &self.document_path {
+                    ui.weak(path.display().to_string());
+                }
+                if let Some(watch) = &self.script_watch {
+                    ui.weak(format!("監視中: {}", watch.path.display()));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("共有リンクをコピー").on_hover_text("Web版で開けるリンクをコピーする").clicked() {
+                    ui.ctx().copy_text(self.shared_state().to_link());
+                }
+                ui.add(TextEdit::singleline(&mut self.share_link_input).hint_text("共有リンク").desired_width(200.0));
+                if ui.add_enabled(!self.share_link_input.trim().is_empty(), egui::Button::new("共有リンクを開く")).clicked() {
+                    match SharedState::from_link(&self.share_link_input) {
+                        Ok(state) => {
+                            self.apply_shared_state(state);
+                            self.share_link_input.clear();
+                        }
+                        Err(e) => self.log_output.borrow_mut().push(LogEntry {
+                            log_type: LogType::Stderr,
+                            message: e,
+                        }),
+                    }
+                }
+            });
+            ui.horizontal(|ui| self.show_export_actions(ui));
+            egui::CollapsingHeader::new("ドキュメント情報").show(ui, |ui| self.show_document_info(ui));
+            egui::CollapsingHeader::new("アニメーションの書き出し").show(ui, |ui| self.show_record_settings(ui));
             let mut layouter = |ui: &egui::Ui, buf: &str, wrap_width: f32| {
                 let mut layout_job = syntax_highlighting::highlight(
                     ui.ctx(),
@@ -245,143 +2879,268 @@ impl App for ParametricPlotApp {
         });
         // #[cfg(target_arch = "wasm32")]
         // subwin.aware();
-        egui::CentralPanel::default().show(ctx, |ui| {            let mut need_redraw = false;
+        egui::CentralPanel::default().show(ctx, |ui| {            let mut need_redraw = std::mem::take(&mut self.redraw_requested);
+
+            // --- アニメーション・シミュレーションなどの進行 ---
+            let frame_dt = ctx.input(|i| i.stable_dt) as f64;
+            if self.advance_frame(frame_dt) {
+                need_redraw = true;
+            }
 
             // グラフエリアのサイズを画面全体に設定
             let available_size = ui.available_size();
 
+            // onDragが定義されている場合やドラッグ点を掴んでいる場合は、プロットのパンを無効にする
+            let has_on_drag = self.engine.has_function("onDrag");
+            let hover_pos = ctx.input(|i| i.pointer.hover_pos());
+            let over_drag_point = match (&self.last_plot_transform, hover_pos) {
+                (Some(transform), Some(pos)) => self.drag_point_at(transform, pos).is_some(),
+                _ => false,
+            };
+            let allow_pan = !has_on_drag && !over_drag_point && self.dragging_point.is_none();
+
             // --- プロット領域の作成（背景として配置）---
-            let plot = Plot::new("parametric_plot")
+            let mut plot = Plot::new("parametric_plot")
                 .show_background(true)
-                .show_axes([true, true])
+                .show_axes([self.plot_options.show_axes, self.plot_options.show_axes])
+                .show_grid(self.plot_options.show_grid)
                 .min_size(available_size)
                 .width(available_size.x)
                 .height(available_size.y)
-                .data_aspect(1.0)
+                .allow_drag(allow_pan)
                 .x_axis_label("x")
                 .y_axis_label("y");
+            if let Some(aspect) = self.plot_options.data_aspect {
+                plot = plot.data_aspect(aspect);
+            }
             // プロット描画
-            plot.show(ui, |plot_ui| {
-                // 多角形を描画
-                for (name, points, color, fill, weight) in self.polygons.borrow().iter() {
-                    let polygon = Polygon::new(name, PlotPoints::new(points.clone()))
-                        .stroke(Stroke::new(*weight, *color));
-                    plot_ui.polygon(polygon);
-                }
-
-                // 通常の曲線を描画
-                for (name, points, color, weight) in self.graph_lines.borrow().iter() {
-                    let line = Line::new(name, PlotPoints::new(points.clone()))
-                        .color(*color)
-                        .width(*weight);
-                    plot_ui.line(line);
-                }
-
-                // ベクトルをLine::newを使用して描画 (本体 + 矢じり2線)
-                const ARROW_HEAD_LENGTH: f64 = 0.15; // 矢じりの各辺の長さ
-                const ARROW_HEAD_ANGLE: f64 = std::f64::consts::PI / 7.0; // 矢じりの角度 (約25.7度)
-
-                for (name, origins_vec, tips_vec, color, weight) in self.vectors.borrow().iter() {
-                    // 現在のJSの実装では、origins_vecとtips_vecは常に要素を1つだけ持つ
-                    if origins_vec.is_empty() || tips_vec.is_empty() {
-                        continue;
-                    }
-                    let origin = origins_vec[0]; // [f64; 2]
-                    let tip = tips_vec[0];       // [f64; 2]
-
-                    // 1. ベクトルの本体を描画
-                    let main_line_points = PlotPoints::new(vec![origin, tip]);
-                    let main_line = Line::new(format!("{}_main", name), main_line_points)
-                        .color(*color)
-                        .width(*weight);
-                    plot_ui.line(main_line);
-
-                    // ベクトルの方向と長さを計算
-                    let vec_dx = tip[0] - origin[0];
-                    let vec_dy = tip[1] - origin[1];
-                    let vec_len = (vec_dx.powi(2) + vec_dy.powi(2)).sqrt();
-
-                    if vec_len < 1e-6 { // ベクトルが非常に短い場合は矢じりを描画しない
-                        continue;
-                    }
+            let pending_bounds = self.pending_plot_bounds.take();
+            let reset_view = std::mem::take(&mut self.reset_plot_view);
+            let plot_response = plot.show(ui, |plot_ui| {
+                // 新規ドキュメントでは表示範囲を自動に戻す
+                if reset_view {
+                    plot_ui.set_auto_bounds(egui::Vec2b::TRUE);
+                }
+                // 共有URLなどで指定された表示範囲を設定
+                if let Some([min_x, min_y, max_x, max_y]) = pending_bounds {
+                    plot_ui.set_plot_bounds(PlotBounds::from_min_max([min_x, min_y], [max_x, max_y]));
+                }
 
-                    // 矢じりの長さを調整 (ベクトル本体が短い場合は矢じりも短くする)
-                    let actual_arrow_head_length = ARROW_HEAD_LENGTH.min(vec_len * 0.4);
-
-                    // 矢じりのための基準ベクトル（tipからoriginへ向かう方向）
-                    let base_arrow_dx_norm = -vec_dx / vec_len;
-                    let base_arrow_dy_norm = -vec_dy / vec_len;
-
-                    // 矢じりの一方の辺の計算
-                    let angle1 = ARROW_HEAD_ANGLE;
-                    let cos_a1 = angle1.cos();
-                    let sin_a1 = angle1.sin();
-                    let arrow1_tip_dx = base_arrow_dx_norm * cos_a1 - base_arrow_dy_norm * sin_a1;
-                    let arrow1_tip_dy = base_arrow_dx_norm * sin_a1 + base_arrow_dy_norm * cos_a1;
-                    let arrow_p1 = [
-                        tip[0] + actual_arrow_head_length * arrow1_tip_dx,
-                        tip[1] + actual_arrow_head_length * arrow1_tip_dy,
-                    ];
+                // draw()で追加された図形を描画
+                plot::show_scene(plot_ui, &self.scene.borrow());
+
+                // ドラッグ点のハンドルを描画
+                for (i, point) in self.drag_points.iter().enumerate() {
+                    let handle = Points::new(point.name.clone(), vec![point.value])
+                        .radius(6.0)
+                        .filled(true)
+                        .color(point.color)
+                        .highlight(self.dragging_point == Some(i));
+                    plot_ui.points(handle);
+                }
 
-                    // 矢じりのもう一方の辺の計算
-                    let angle2 = -ARROW_HEAD_ANGLE; // 反対側の角度
-                    let cos_a2 = angle2.cos();
-                    let sin_a2 = angle2.sin();
-                    let arrow2_tip_dx = base_arrow_dx_norm * cos_a2 - base_arrow_dy_norm * sin_a2;
-                    let arrow2_tip_dy = base_arrow_dx_norm * sin_a2 + base_arrow_dy_norm * cos_a2;
-                    let arrow_p2 = [
-                        tip[0] + actual_arrow_head_length * arrow2_tip_dx,
-                        tip[1] + actual_arrow_head_length * arrow2_tip_dy,
-                    ];
+                plot_ui.pointer_coordinate()
+            });
 
-                    // 2. 矢じりの線1を描画
-                    plot_ui.line(Line::new(format!("{}_arrow1", name), PlotPoints::new(vec![tip, arrow_p1])).color(*color).width(*weight));
-                    // 3. 矢じりの線2を描画
-                    plot_ui.line(Line::new(format!("{}_arrow2", name), PlotPoints::new(vec![tip, arrow_p2])).color(*color).width(*weight));
+            // --- ドラッグ点の操作 ---
+            {
+                let response = &plot_response.response;
+                let transform = &plot_response.transform;
+                if response.drag_started_by(egui::PointerButton::Primary)
+                    && let Some(origin) = ctx.input(|i| i.pointer.press_origin())
+                {
+                    self.dragging_point = self.drag_point_at(transform, origin);
                 }
-            });
+                if let Some(index) = self.dragging_point {
+                    if index >= self.drag_points.len() || response.drag_stopped() {
+                        self.dragging_point = None;
+                    } else if let Some(pos) = response.interact_pointer_pos() {
+                        let target = transform.value_from_position(pos);
+                        if self.move_drag_point(index, [target.x, target.y]) {
+                            need_redraw = true;
+                        }
+                    }
+                }
+                self.last_plot_transform = Some(plot_response.transform);
+                let bounds = plot_response.transform.bounds();
+                self.plot_bounds = Some([bounds.min()[0], bounds.min()[1], bounds.max()[0], bounds.max()[1]]);
+            }
+
+            // --- ポインタ・キーボードイベントをスクリプトに通知 ---
+            if let Some(pointer) = plot_response.inner {
+                let response = &plot_response.response;
+                let (x, y) = (pointer.x, pointer.y);
+                // buttonはDOMのMouseEvent.buttonと同じ番号 (0: 左, 1: 中, 2: 右)
+                for (button, code) in [
+                    (egui::PointerButton::Primary, 0),
+                    (egui::PointerButton::Middle, 1),
+                    (egui::PointerButton::Secondary, 2),
+                ] {
+                    if response.clicked_by(button)
+                        && self.call_js_hook("onClick", &[x.into(), y.into(), f64::from(code).into()])
+                    {
+                        need_redraw = true;
+                    }
+                }
+                if response.dragged_by(egui::PointerButton::Primary)
+                    && self.dragging_point.is_none()
+                    && self.call_js_hook("onDrag", &[x.into(), y.into()])
+                {
+                    need_redraw = true;
+                }
+                // onHoverはポインタが動いたときだけ呼び出す
+                if response.hovered() && self.last_hover_coordinate != Some([x, y]) {
+                    self.last_hover_coordinate = Some([x, y]);
+                    if self.call_js_hook("onHover", &[x.into(), y.into()]) {
+                        need_redraw = true;
+                    }
+                }
+            }
+            // テキスト入力中やスライダなどにフォーカスがあるときのキー操作はスクリプトに渡さない
+            if ctx.memory(|m| m.focused()).is_none() {
+                let keys: Vec<egui::Key> = ctx.input(|i| {
+                    i.events.iter().filter_map(|event| match event {
+                        // 押し続けたときの自動リピートは除く
+                        egui::Event::Key { key, pressed: true, repeat: false, .. } => Some(*key),
+                        _ => None,
+                    }).collect()
+                });
+                for key in keys {
+                    if self.call_js_hook("onKey", &[key.name().into()]) {
+                        need_redraw = true;
+                    }
+                }
+            }
 
             // --- スライダー・チェックボックスを重ねて表示 ---
-            if !self.sliders.is_empty() || !self.checkboxes.is_empty() || !self.color_pickers.is_empty() {
+            if self.has_parameter_panel() {
+                let mut clicked_button = None;
                 // 左上にパラメータパネルを配置
-                let subwin = egui::Window::new("パラメータ")
+                let _subwin = egui::Window::new("パラメータ")
                     .resizable(true)
                     .show(ctx, |ui| {
                         ui.set_min_width(100.0);
-                        for slider in &mut self.sliders {
-                            let widget = CustomSlider::new(slider);
-                            if ui.add(widget).changed() {
+                        if self.show_presets(ui) {
+                            need_redraw = true;
+                        }
+                        ui.separator();
+                        if self.is_animated() {
+                            let mut step_requested = false;
+                            ui.horizontal(|ui| {
+                                let label = if self.animation.playing { "⏸ 一時停止" } else { "▶ 再生" };
+                                if ui.button(label).clicked() {
+                                    self.animation.playing = !self.animation.playing;
+                                }
+                                if ui.add_enabled(!self.animation.playing, egui::Button::new("⏭ コマ送り")).clicked() {
+                                    step_requested = true;
+                                }
+                                if ui.button("⏮ リセット").clicked() {
+                                    self.animation.time = 0.0;
+                                    self.animation.frame = 0;
+                                    for anim in &mut self.animations {
+                                        anim.value = anim.min;
+                                    }
+                                    need_redraw = true;
+                                }
+                            });
+                            ui.add(Slider::new(&mut self.animation.speed, 0.1..=4.0).logarithmic(true).text("速度"));
+                            ui.label(format!("time: {:.2}  frame: {}", self.animation.time, self.animation.frame));
+                            for anim in &self.animations {
+                                ui.label(format!("{}: {:.3}", anim.name, anim.value));
+                            }
+                            if step_requested {
+                                self.advance_animation(ANIMATION_STEP_DT);
                                 need_redraw = true;
                             }
+                            ui.separator();
                         }
-                        for checkbox in &mut self.checkboxes {
-                            if ui.checkbox(&mut checkbox.value, &checkbox.label).changed() {
+                        if self.simulation.enabled {
+                            let mut step_requested = false;
+                            let mut reset_requested = false;
+                            ui.horizontal(|ui| {
+                                let label = if self.simulation.running { "⏸ 停止" } else { "▶ 実行" };
+                                if ui.button(label).clicked() {
+                                    self.simulation.running = !self.simulation.running;
+                                    self.simulation.accumulator = 0.0;
+                                }
+                                if ui.add_enabled(!self.simulation.running, egui::Button::new("⏭ ステップ")).clicked() {
+                                    step_requested = true;
+                                }
+                                if ui.button("⏮ リセット").clicked() {
+                                    reset_requested = true;
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("dt");
+                                ui.add(egui::DragValue::new(&mut self.simulation.timestep)
+                                    .speed(0.001)
+                                    .range(0.001..=1.0));
+                                ui.label(format!("step: {}", self.simulation.steps));
+                            });
+                            if reset_requested {
+                                self.reset_simulation();
                                 need_redraw = true;
                             }
+                            if step_requested {
+                                self.register_control_globals();
+                                self.step_simulation();
+                                need_redraw = true;
+                            }
+                            ui.separator();
                         }
-                        for picker in &mut self.color_pickers {
-                            ui.horizontal(|ui| {
-                                ui.label(&picker.name);
-                                // Color32 (u8 0-255 per channel) を [f32; 3] (0.0-1.0 per channel) に変換
-                                let mut color_f32 = [
-                                    picker.value.r() as f32 / 255.0,
-                                    picker.value.g() as f32 / 255.0,
-                                    picker.value.b() as f32 / 255.0,
-                                ];
-                                if ui.color_edit_button_rgb(&mut color_f32).changed() {
-                                    // [f32; 3] から Color32 に戻す (アルファは常に255)
-                                    picker.value = Color32::from_rgb((color_f32[0] * 255.0) as u8, (color_f32[1] * 255.0) as u8, (color_f32[2] * 255.0) as u8);
-                                    need_redraw = true;
+                        // 宣言順に表示する。グループに属する要素は、そのグループが最初に現れた位置にまとめて折りたたみ表示する
+                        let layout = self.layout.clone();
+                        let mut shown_groups: Vec<&str> = Vec::new();
+                        for entry in &layout {
+                            let members: Vec<&ControlEntry> = match &entry.group {
+                                None => vec![entry],
+                                Some(group) if shown_groups.contains(&group.as_str()) => continue,
+                                Some(group) => {
+                                    shown_groups.push(group);
+                                    layout.iter().filter(|e| e.group.as_ref() == Some(group)).collect()
+                                }
+                            };
+                            let mut show_members = |ui: &mut Ui| {
+                                for member in &members {
+                                    let response = self.show_control(ui, member);
+                                    if member.kind == ControlKind::Button {
+                                        if response.clicked() {
+                                            clicked_button = Some(member.index);
+                                        }
+                                    } else if response.changed() {
+                                        need_redraw = true;
+                                    }
+                                }
+                            };
+                            match &entry.group {
+                                None => show_members(ui),
+                                Some(group) => {
+                                    egui::CollapsingHeader::new(group.as_str())
+                                        .default_open(true)
+                                        .show(ui, show_members);
                                 }
-                            });
+                            }
                         }
                     });
                 #[cfg(target_arch = "wasm32")]
-                subwin.aware();
+                _subwin.aware();
+
+                // ボタンのコールバックを呼び出す
+                if let Some(i) = clicked_button {
+                    let callback = self.buttons[i].callback.clone();
+                    self.register_control_globals();
+                    if let Err(e) = self.engine.call_function(&callback, &[]) {
+                        self.log_output.borrow_mut().push(LogEntry {
+                            log_type: LogType::Stderr,
+                            message: format!("{}: {}", self.buttons[i].label, e),
+                        });
+                    }
+                    need_redraw = true;
+                }
             }
 
             // --- ログ出力ウィンドウ ---
-            let subwin = egui::Window::new("出力ログ")
+            let _subwin = egui::Window::new("出力ログ")
                 .default_size([600.0, 200.0]) // デフォルトサイズを調整
                 .resizable(true)
                 .show(ctx, |ui| {
@@ -423,417 +3182,25 @@ impl App for ParametricPlotApp {
                         }});
                 });
             #[cfg(target_arch = "wasm32")]
-            subwin.aware();
+            _subwin.aware();
 
             // --- JavaScript関連の処理 ---
             // JSコードが変更された場合は再評価
             if js_code_changed || !self.js_code_evaluated || self.js_code != self.last_js_code {
-                #[cfg(target_arch = "wasm32")]
-                {
-                    use web_sys::Url;
-                    if let Some(window) = web_sys::window() {
-                        if let Ok(href) = window.location().href() {
-                            if let Ok(url) = Url::new(&href) {
-                                // 既存クエリをパース
-                                let search = url.search();
-                                let mut params: Vec<(String, String)> = vec![];
-                                if !search.is_empty() {
-                                    for (k, v) in form_urlencoded::parse(search.trim_start_matches('?').as_bytes()) {
-                                        if k != "code" {
-                                            params.push((k.into_owned(), v.into_owned()));
-                                        }
-                                    }
-                                }
-                                // codeパラメータをbase64で追加
-                                let code_b64 = general_purpose::URL_SAFE_NO_PAD.encode(&self.js_code);
-                                params.push(("code".to_string(), code_b64));
-                                // クエリ再構築
-                                let mut ser = Serializer::new(String::new());
-                                for (k, v) in params {
-                                    ser.append_pair(&k, &v);
-                                }
-                                let new_query = ser.finish();
-                                url.set_search(&format!("?{}", new_query));
-                                let _ = window.history().expect("history").replace_state_with_url(&wasm_bindgen::JsValue::NULL, "", Some(&url.href()));
-                            }
-                        }
-                    }
-                }
-
-                self.js_code_evaluated = false;
-                self.last_js_code = self.js_code.clone();
-
-                // Rust側stdout/stderrをJSに提供
-                let log_output_stdout = self.log_output.clone();
-                let stdout = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let content = args.get_or_undefined(0).to_string(context)?;
-                    let msg = content.to_std_string().unwrap_or_else(|e| format!("[stdout conversion error: {:?}]", e));
-                    println!("[JS stdout]: {}", msg); // Keep original console log
-                    log_output_stdout.borrow_mut().push(LogEntry {
-                        log_type: LogType::Stdout,
-                        message: msg,
-                    });
-                    Ok(JsValue::undefined())
-                };
-                unsafe {
-                    self.js_context.register_global_builtin_callable("stdout".into(), 1, NativeFunction::from_closure(stdout)).unwrap();
-                }
-
-                // stderr
-                let log_output_stderr = self.log_output.clone();
-                let stderr = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let content = args.get_or_undefined(0).to_string(context)?;
-                    let msg = content.to_std_string().unwrap_or_else(|e| format!("[stderr conversion error: {:?}]", e));
-                    #[cfg(not(target_arch = "wasm32"))]
-                    eprintln!("[JS stderr]: {}", msg.clone().red());
-                    #[cfg(target_arch = "wasm32")]
-                    web_sys::console::error_1(&msg.clone().into());
-                    log_output_stderr.borrow_mut().push(LogEntry {
-                        log_type: LogType::Stderr,
-                        message: msg,
-                    });
-                    Ok(JsValue::undefined())
-                };
-                unsafe {
-                    self.js_context.register_global_builtin_callable("stderr".into(), 1, NativeFunction::from_closure(stderr)).unwrap();
-                }
-
-                // JS側でconsole.log/console.errorをstdout/stderr経由でJSON出力するように定義
-                let console_js = r#"
-                    try {
-                        if (typeof globalThis.console !== 'object' || globalThis.console === null) {
-                            globalThis.console = {};
-                        }
-                        globalThis.console.log = function(...args) {
-                            try { stdout(args.map(x=>JSON.stringify(x)).join(" ")); } catch(e) {}
-                        };
-                        globalThis.console.error = function(...args) {
-                            try { stderr(args.map(x=>JSON.stringify(x)).join(" ")); } catch(e) {}
-                        };
-                    } catch(e) { stderr('[console patch error] ' + e); }
-                "#;
-                if let Err(e) = self.js_context.eval(Source::from_bytes(console_js)) {
-                    println!("Error setting up console: {:?}", e);
-                }
-
-                let sliders_rc = Rc::new(RefCell::new(Vec::new()));
-                let sliders_api = sliders_rc.clone();
-                let checkboxes_rc = Rc::new(RefCell::new(Vec::new()));
-                let checkboxes_api = checkboxes_rc.clone();
-                let color_pickers_rc = Rc::new(RefCell::new(Vec::new()));
-                let color_pickers_api = color_pickers_rc.clone();
-                let graph_lines_api = self.graph_lines.clone();
-                let vectors_api = self.vectors.clone();
-                let polygons_api = self.polygons.clone();
-
-                // addSlider API
-                let add_slider = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let name = args.get_or_undefined(0).to_string(context)?;
-                    let params = args.get_or_undefined(1).to_object(context)?;
-                    
-                    let min = params.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("min")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0);
-                    let max = params.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("max")), context).and_then(|v| v.to_number(context)).unwrap_or(1.0);
-                    let step = params.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("step")), context).and_then(|v| v.to_number(context)).unwrap_or(0.001);
-                    let default = params.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("default")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0);
-                    
-                    sliders_api.borrow_mut().push(SliderParam {
-                        name: name.to_std_string().unwrap(),
-                        min,
-                        max,
-                        step,
-                        value: default,
-                    });
-                    Ok(JsValue::undefined())
-                };
-                unsafe { self.js_context.register_global_builtin_callable("addSlider".into(), 2, NativeFunction::from_closure(add_slider)).unwrap(); }
-
-                // addCheckbox API
-                let add_checkbox = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let name = args.get_or_undefined(0).to_string(context)?;
-                    let label = args.get_or_undefined(1).to_string(context)?;
-                    let params = args.get_or_undefined(2).to_object(context)?;
-                    
-                    let default = if let Ok(default_val) = params.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("default")), context) {
-                        default_val.as_boolean().unwrap_or(true)
-                    } else {
-                        true
-                    };
-
-                    checkboxes_api.borrow_mut().push(CheckboxParam {
-                        name: name.to_std_string().unwrap(),
-                        label: label.to_std_string().unwrap(),
-                        value: default,
-                    });
-                    Ok(JsValue::undefined())
-                };
-                unsafe { self.js_context.register_global_builtin_callable("addCheckbox".into(), 3, NativeFunction::from_closure(add_checkbox)).unwrap(); }
-
-                // addColorpicker API
-                let add_color_picker = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let name = args.get_or_undefined(0).to_string(context)?;
-                    let params = args.get_or_undefined(1).to_object(context)?;
-                    let mut default_color_val = Color32::from_rgb(255, 255, 255); // デフォルトは白
-                    if let Ok(js_val) = params.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("default")), context) {
-                        if let Some(obj) = js_val.as_object() {
-                            if obj.is_array() {
-                                let len = obj.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("length")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0) as usize;
-                                if len >= 3 {
-                                    let r = obj.get(0, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(255.0) as u8;
-                                    let g = obj.get(1, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(255.0) as u8;
-                                    let b = obj.get(2, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(255.0) as u8;
-                                    default_color_val = Color32::from_rgb(r, g, b);
-                                }
-                            }
-                        }
-                    }
-                    color_pickers_api.borrow_mut().push(ColorPickerParam {
-                        name: name.to_std_string().unwrap(),
-                        value: default_color_val,
-                    });
-                    Ok(JsValue::undefined())
-                };
-                unsafe { self.js_context.register_global_builtin_callable("addColorpicker".into(), 2, NativeFunction::from_closure(add_color_picker)).unwrap(); }
-
-                // addParametricGraph API
-                let add_parametric_graph = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let name = args.get_or_undefined(0).to_string(context)?;
-                    let f = args.get_or_undefined(1).as_object()
-                        .ok_or_else(|| boa_engine::JsNativeError::typ().with_message("Second argument must be a function"))?;
-                    let range = args.get_or_undefined(2).to_object(context)?;
-                    let style = args.get_or_undefined(3).to_object(context)?;                    let min: f64 = range.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("min")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0);
-                    let max: f64 = range.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("max")), context).and_then(|v| v.to_number(context)).unwrap_or(2.0 * std::f64::consts::PI);
-                    let num_points: f64 = range.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("num_points")), context).and_then(|v| v.to_number(context)).unwrap_or(500.0);
-                    // deltaは必ず計算する（rangeから取得しない）
-                    let delta: f64 = (max - min) / num_points;
-                    const DEFAULT_GRAPH_COLOR: Color32 = Color32::from_rgb(200, 100, 0);
-                    const DEFAULT_GRAPH_WEIGHT: f32 = 1.5;
-                    let mut line_color = DEFAULT_GRAPH_COLOR;
-                    let mut line_weight = DEFAULT_GRAPH_WEIGHT;
-                    if let Ok(js_val) = style.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("color")), context) {
-                        if let Some(obj) = js_val.as_object() {
-                            if obj.is_array() {
-                                let len = obj.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("length")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0) as usize;
-                                if len >= 3 {
-                                    let r = obj.get(0, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(200.0) as u8;
-                                    let g = obj.get(1, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(100.0) as u8;
-                                    let b = obj.get(2, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0) as u8;
-                                    line_color = Color32::from_rgb(r, g, b);
-                                }
-                            }
-                        }
-                    }
-                    if let Ok(weight) = style.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("weight")), context) {
-                        line_weight = weight.to_number(context).unwrap_or(DEFAULT_GRAPH_WEIGHT as f64) as f32;
-                    }                    let mut points = Vec::with_capacity(num_points as usize);
-                    let mut t = min;
-                    // 最後の点まで確実に生成するためのループ
-                    for _ in 0..=num_points as usize {
-                        let args = [JsValue::from(t)];
-                        if let Ok(result) = f.call(&JsValue::undefined(), &args, context) {
-                            if let Some(obj) = result.as_object() {
-                                if obj.is_array() {
-                                    let len = obj.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("length")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0) as usize;
-                                    if len >= 2 {
-                                        let x = obj.get(0, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0);
-                                        let y = obj.get(1, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0);
-                                        points.push([x, y]);
-                                    }
-                                }
-                            }
-                        }
-                        t += delta;
-                    }
-                    graph_lines_api.borrow_mut().push((name.to_std_string().unwrap(), points, line_color, line_weight));
-                    Ok(JsValue::undefined())
-                };
-                unsafe { self.js_context.register_global_builtin_callable("addParametricGraph".into(), 4, NativeFunction::from_closure(add_parametric_graph)).unwrap(); }
-
-                // addVector API (api.md仕様)
-                let add_vector = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| {
-                    let name = args.get_or_undefined(0).to_string(context)?;
-                    let start_func = args.get_or_undefined(1).as_object()
-                        .ok_or_else(|| boa_engine::JsNativeError::typ().with_message("Second argument must be a function"))?;
-                    let vec_func = args.get_or_undefined(2).as_object()
-                        .ok_or_else(|| boa_engine::JsNativeError::typ().with_message("Third argument must be a function"))?;
-                    let t = args.get_or_undefined(3).to_number(context)?;
-                    let style = args.get_or_undefined(4).to_object(context)?;
-                    // デフォルト色・太さ
-                    let mut color = Color32::from_rgb(0, 150, 200);
-                    let mut weight = 1.5;
-                    if let Ok(js_val) = style.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("color")), context) {
-                        if let Some(obj) = js_val.as_object() {
-                            if obj.is_array() {
-                                let len = obj.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("length")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0) as usize;
-                                if len >= 3 {
-                                    let r = obj.get(0, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0) as u8;
-                                    let g = obj.get(1, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(150.0) as u8;
-                                    let b = obj.get(2, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(200.0) as u8;
-                                    color = Color32::from_rgb(r, g, b);
-                                }
-                            }
-                        }
-                    }
-                    if let Ok(weight_val) = style.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("weight")), context) {
-                        weight = weight_val.to_number(context).unwrap_or(1.5) as f32;
-                    }
-                    // tで関数を呼び出し
-                    let args_t = [JsValue::from(t)];
-                    let start = if let Ok(result) = start_func.call(&JsValue::undefined(), &args_t, context) {
-                        if let Some(obj) = result.as_object() {
-                            if obj.is_array() {
-                                let len = obj.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("length")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0) as usize;
-                                if len >= 2 {
-                                    let x = obj.get(0, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0);
-                                    let y = obj.get(1, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0);
-                                    Some([x, y])
-                                } else { None }
-                            } else { None }
-                        } else { None }
-                    } else { None };
-                    let vec = if let Ok(result) = vec_func.call(&JsValue::undefined(), &args_t, context) {
-                        if let Some(obj) = result.as_object() {
-                            if obj.is_array() {
-                                let len = obj.get(boa_engine::property::PropertyKey::from(boa_engine::JsString::from("length")), context).and_then(|v| v.to_number(context)).unwrap_or(0.0) as usize;
-                                if len >= 2 {
-                                    let dx = obj.get(0, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0);
-                                    let dy = obj.get(1, context).unwrap_or(JsValue::undefined()).to_number(context).unwrap_or(0.0);
-                                    Some([dx, dy])
-                                } else { None }
-                            } else { None }
-                        } else { None }
-                    } else { None };
-                    let mut origins_vec = Vec::new();
-                    let mut tips_vec = Vec::new();
-                    if let (Some(start), Some(vec)) = (start, vec) {
-                        origins_vec.push(start);
-                        tips_vec.push([start[0] + vec[0], start[1] + vec[1]]);
-                    }
-                    vectors_api.borrow_mut().push((name.to_std_string().unwrap(), origins_vec, tips_vec, color, weight));
-                    Ok(JsValue::undefined())
-                };
-                unsafe { self.js_context.register_global_builtin_callable("addVector".into(), 5, NativeFunction::from_closure(add_vector)).unwrap(); }
-
-                // addPolygon API
-                let add_polygon = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| -> Result<JsValue, _> {
-                    let name = args.get_or_undefined(0).to_string(context)?.to_std_string().unwrap_or_default();                    let points_array = args.get_or_undefined(1).as_object()
-                        .ok_or_else(|| JsNativeError::error().with_message("Expected array for points"))?;
-                    let style_obj = args.get_or_undefined(2).as_object();
-
-                    let mut points = Vec::new();                    let length = {
-                        let key: PropertyKey = js_string!("length").into();
-                        points_array.get(key, context)?.as_number().unwrap_or(0.0) as u32
-                    };
-                    for i in 0..length {
-                        if let Some(point) = points_array.get(i, context)?.as_object() {
-                            let x = point.get(0, context)?.as_number().unwrap_or(0.0);
-                            let y = point.get(1, context)?.as_number().unwrap_or(0.0);
-                            points.push([x, y]);
-                        }
-                    }
-
-                    let default_color = Color32::from_rgb(0, 0, 0);
-                    let default_fill = Color32::from_rgb(128, 128, 255);
-                    let default_weight = 1.5;                    let (color, fill, weight) = if let Some(style) = style_obj {                        let color = {
-                            let key: PropertyKey = js_string!("color").into();
-                            if let Some(color_arr) = style.get(key, context)?.as_object() {
-                                Color32::from_rgb(
-                                    color_arr.get(0, context)?.as_number().unwrap_or(0.0) as u8,
-                                    color_arr.get(1, context)?.as_number().unwrap_or(0.0) as u8,
-                                    color_arr.get(2, context)?.as_number().unwrap_or(0.0) as u8,
-                                )
-                            } else {
-                                default_color
-                            }
-                        };
-
-                        let fill = {
-                            let key: PropertyKey = js_string!("fill").into();
-                            if let Some(fill_arr) = style.get(key, context)?.as_object() {
-                                Color32::from_rgb(
-                                    fill_arr.get(0, context)?.as_number().unwrap_or(0.0) as u8,
-                                    fill_arr.get(1, context)?.as_number().unwrap_or(0.0) as u8,
-                                    fill_arr.get(2, context)?.as_number().unwrap_or(0.0) as u8,
-                                )
-                            } else {
-                                default_fill
-                            }
-                        };
-
-                        let weight = {
-                            let key: PropertyKey = js_string!("weight").into();
-                            style.get(key, context)?.as_number().unwrap_or(default_weight as f64) as f32
-                        };
-
-                        (color, fill, weight)
-                    } else {
-                        (default_color, default_fill, default_weight)
-                    };
-
-                    polygons_api.borrow_mut().push((name, points, color, fill, weight));
-                    Ok(JsValue::undefined())
-                };
-
-                unsafe {
-                    self.js_context.register_global_builtin_callable("addPolygon".into(), 3, NativeFunction::from_closure(add_polygon)).unwrap();
-                }
-
-                // ログ出力をリセット
-                self.log_output.borrow_mut().clear();
-
-                // コードの読み込み
-                println!("load");
-                if let Err(e) = self.js_context.eval(Source::from_bytes(self.js_code.as_str())) {
-                    println!("Load error: {:?}", e);
-                }
-
-                // Setup関数の実行
-                println!("setup");
-                if let Err(e) = self.js_context.eval(Source::from_bytes("try {setup();} catch (e) {stderr(e.toString()+'\\n'+e.stack);}")) {
-                    println!("Setup error: {:?}", e);
-                }
-
-                self.sliders = sliders_rc.borrow().clone();
-                self.checkboxes = checkboxes_rc.borrow().clone();
-                self.color_pickers = color_pickers_rc.borrow().clone();
+                self.evaluate_script();
                 need_redraw = true;
-
-                self.js_code_evaluated = true;
             }
 
             // グラフの再描画フラグ
-            if need_redraw || self.graph_lines.borrow().is_empty() {
-                // UI値をグローバル変数として注入
-                for slider in &self.sliders {
-                    self.js_context.register_global_property::<PropertyKey, f64>(js_string!(slider.name.clone()).into(), slider.value, Attribute::all()).ok();
-                }
-                for checkbox in &self.checkboxes {
-                    self.js_context.register_global_property::<PropertyKey, bool>(js_string!(checkbox.name.clone()).into(), checkbox.value, Attribute::all()).ok();
-                }
-                for picker in &self.color_pickers {
-                    // Array(3)をグローバルから作成
-                    let global = self.js_context.global_object();
-                    let array_ctor = global.get(js_string!("Array"), &mut self.js_context).unwrap();
-                    let array_constructor = array_ctor.as_constructor().expect("Array is not a constructor");
-                    let arr = array_constructor.construct(&[JsValue::from(3)], None, &mut self.js_context).unwrap();
-
-                    // 配列に色の値をセット
-                    arr.set(0, JsValue::from(picker.value.r() as u32), false, &mut self.js_context).ok();
-                    arr.set(1, JsValue::from(picker.value.g() as u32), false, &mut self.js_context).ok();
-                    arr.set(2, JsValue::from(picker.value.b() as u32), false, &mut self.js_context).ok();
-
-                    // グローバル変数として設定
-                    self.js_context.register_global_property::<PropertyKey, JsObject>(js_string!(picker.name.clone()).into(), arr, Attribute::all()).ok();
-                }
-
-                self.graph_lines.borrow_mut().clear();
-                self.vectors.borrow_mut().clear();
-                self.polygons.borrow_mut().clear();
-                if let Err(e) = self.js_context.eval(Source::from_bytes("try {draw();} catch (e) {stderr(e.toString()+'\\n'+e.stack);}")) {
-                    println!("Draw error: {:?}", e);
-                }
+            if need_redraw || self.scene.borrow().is_empty() {
+                self.run_draw();
             }
         });
 
+        self.record_history(ctx);
+        #[cfg(target_arch = "wasm32")]
+        self.sync_url(ctx);
+
         #[cfg(target_arch = "wasm32")]
         hframe::HtmlWindow::new("Monaco Editor").content("").show(ctx);
         #[cfg(target_arch = "wasm32")]
@@ -852,6 +3219,15 @@ pub fn update(data: &str) {
     }
 }
 
+// ブラウザの戻る・進むでURLが変わったときにページから呼び出す
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn open_query(search: &str) {
+    if let Ok(mut query) = PENDING_QUERY.try_lock() {
+        *query = Some(search.to_string());
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(module = "/src/api.js")]
 extern "C" {
@@ -861,27 +3237,169 @@ extern "C" {
 
 struct CustomSlider<'a> {
     param: &'a mut SliderParam,
+    label: Option<String>, // 表示名 (省略時は変数名)
 }
 
 impl<'a> CustomSlider<'a> {
     fn new(param: &'a mut SliderParam) -> Self {
-        Self { param }
+        Self { param, label: None }
+    }
+
+    fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
     }
 }
 
 impl<'a> Widget for CustomSlider<'a> {
     fn ui(self, ui: &mut Ui) -> Response {
+        // Shiftを押しながらドラッグしたときの移動量の倍率
+        const FINE_DRAG_FACTOR: f64 = 0.1;
+
         let param = self.param;
-        let _id = ui.make_persistent_id(&param.name);
+        let label = self.label.unwrap_or_else(|| param.name.clone());
+        let id = ui.make_persistent_id(&param.name);
+        let edit_id = id.with("value_edit"); // 数値入力中の文字列
+        let raw_id = id.with("raw_position"); // 丸める前のスライダ上の位置 (0.0〜1.0)
 
         ui.vertical(|ui| {
-            ui.label(format!("{}: {}", &param.name, param.value));
+            let mut edited_value = None;
+            ui.horizontal(|ui| {
+                ui.label(format!("{}:", label));
+                // 値をダブルクリックすると数値入力欄に切り替わる
+                let editing: Option<String> = ui.data(|d| d.get_temp(edit_id));
+                if let Some(mut text) = editing {
+                    let text_response = ui.add(TextEdit::singleline(&mut text).desired_width(80.0));
+                    if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                        ui.data_mut(|d| d.remove::<String>(edit_id));
+                    } else if text_response.lost_focus() {
+                        if let Ok(value) = text.trim().parse::<f64>() {
+                            edited_value = Some(param.quantize(value));
+                        }
+                        ui.data_mut(|d| d.remove::<String>(edit_id));
+                    } else {
+                        text_response.request_focus();
+                        ui.data_mut(|d| d.insert_temp(edit_id, text));
+                    }
+                } else {
+                    let value_label = ui.add(egui::Label::new(param.format_value(param.value)).sense(Sense::click()))
+                        .on_hover_text("ダブルクリックで数値を入力");
+                    if value_label.double_clicked() {
+                        ui.data_mut(|d| d.insert_temp(edit_id, param.format_value(param.value)));
+                    }
+                }
+                // 自動再生の操作
+                let play_label = if param.playback.playing { "⏸" } else { "▶" };
+                if ui.small_button(play_label).on_hover_text("自動再生").clicked() {
+                    param.playback.playing = !param.playback.playing;
+                    // 1回再生が終わった状態から再生し直す場合は先頭に戻す
+                    if param.playback.playing && param.playback.mode == PlayMode::Once && param.value >= param.max {
+                        param.value = param.min;
+                    }
+                    param.playback.direction = 1.0;
+                }
+                egui::ComboBox::from_id_salt(id.with("play_mode"))
+                    .selected_text(param.playback.mode.label())
+                    .width(60.0)
+                    .show_ui(ui, |ui| {
+                        for mode in [PlayMode::Once, PlayMode::Loop, PlayMode::Bounce] {
+                            ui.selectable_value(&mut param.playback.mode, mode, mode.label());
+                        }
+                    });
+                ui.add(egui::DragValue::new(&mut param.playback.speed)
+                    .speed(0.01)
+                    .range(0.0..=f64::INFINITY)
+                    .prefix("速度 "));
+            });
 
             let desired_size = egui::vec2(ui.available_width(), 20.0);
-            let (rect, mut response) = ui.allocate_exact_size(desired_size, Sense::drag());
+            let (rect, mut response) = ui.allocate_exact_size(desired_size, Sense::click_and_drag());
+            let mut new_value = edited_value;
+
+            // --- マウス操作 ---
+            // クリックした位置へ移動し、ドラッグ中はハンドルがカーソルに追従する
+            // Shiftを押しながらのドラッグでは、移動量を小さくして微調整する
+            if response.drag_started() || response.clicked() {
+                response.request_focus();
+            }
+            if (response.dragged() || response.clicked())
+                && let Some(pos) = response.interact_pointer_pos()
+            {
+                let fine = response.dragged() && ui.input(|i| i.modifiers.shift);
+                let position = if fine {
+                    let previous: f64 = ui.data(|d| d.get_temp(raw_id)).unwrap_or_else(|| param.to_normalized(param.value));
+                    let delta_x = ui.input(|i| i.pointer.delta().x) as f64;
+                    previous + delta_x / rect.width() as f64 * FINE_DRAG_FACTOR
+                } else {
+                    ((pos.x - rect.left()) / rect.width()) as f64
+                };
+                let position = position.clamp(0.0, 1.0);
+                // 丸める前の位置を保持しておき、微調整が丸め誤差で止まらないようにする
+                ui.data_mut(|d| d.insert_temp(raw_id, position));
+                new_value = Some(param.quantize(param.normalized_to_value(position)));
+            }
+            if response.drag_stopped() {
+                ui.data_mut(|d| d.remove::<f64>(raw_id));
+            }
 
-            // スライダの「棒」の色をテーマに合わせる
-            let line_color = ui.visuals().widgets.inactive.bg_fill;
+            // --- キーボード操作 (フォーカス中) ---
+            // ←↓/→↑: 1刻み, PageDown/PageUp: 10刻み, Home/End: 最小値/最大値
+            if response.has_focus() {
+                ui.memory_mut(|m| {
+                    m.set_focus_lock_filter(
+                        response.id,
+                        egui::EventFilter {
+                            horizontal_arrows: true,
+                            vertical_arrows: true,
+                            ..Default::default()
+                        },
+                    );
+                });
+                let (decrement, increment, page_down, page_up, home, end) = ui.input_mut(|i| {
+                    (
+                        i.count_and_consume_key(egui::Modifiers::NONE, egui::Key::ArrowLeft)
+                            + i.count_and_consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                        i.count_and_consume_key(egui::Modifiers::NONE, egui::Key::ArrowRight)
+                            + i.count_and_consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                        i.count_and_consume_key(egui::Modifiers::NONE, egui::Key::PageDown),
+                        i.count_and_consume_key(egui::Modifiers::NONE, egui::Key::PageUp),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::Home),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::End),
+                    )
+                });
+                let mut value = new_value.unwrap_or(param.value);
+                let small_steps = increment as f64 - decrement as f64;
+                let large_steps = page_up as f64 - page_down as f64;
+                if small_steps != 0.0 {
+                    value = param.nudge(value, small_steps);
+                }
+                if large_steps != 0.0 {
+                    value = param.nudge(value, large_steps * 10.0);
+                }
+                if home {
+                    value = param.min;
+                }
+                if end {
+                    value = param.max;
+                }
+                if small_steps != 0.0 || large_steps != 0.0 || home || end {
+                    new_value = Some(value);
+                }
+            }
+
+            if let Some(new_value) = new_value
+                && param.value != new_value
+            {
+                param.value = new_value;
+                response.mark_changed();
+            }
+
+            // スライダの「棒」の色をテーマに合わせる (フォーカス中は強調する)
+            let line_color = if response.has_focus() {
+                ui.visuals().selection.bg_fill
+            } else {
+                ui.visuals().widgets.inactive.bg_fill
+            };
             let line_y = rect.center().y;
             let line_start = Pos2::new(rect.left(), line_y);
             let line_end = Pos2::new(rect.right(), line_y);
@@ -894,13 +3412,13 @@ impl<'a> Widget for CustomSlider<'a> {
             } else {
                 ui.visuals().widgets.inactive.bg_fill
             };
-            let border_color = if response.dragged() || response.hovered() {
+            let border_color = if response.dragged() || response.hovered() || response.has_focus() {
                 ui.visuals().widgets.active.fg_stroke.color
             } else {
                 ui.visuals().widgets.inactive.fg_stroke.color
             };
 
-            let normalized = (param.value - param.min) / (param.max - param.min);
+            let normalized = param.to_normalized(param.value);
             let handle_pos = Pos2::new(
                 rect.left() + (normalized * rect.width() as f64) as f32,
                 line_y,
@@ -914,26 +3432,511 @@ impl<'a> Widget for CustomSlider<'a> {
             let handle_radius = 8.0;
             ui.painter().circle_filled(handle_pos, handle_radius, handle_color);
 
-            if response.dragged() {
-                let delta_x = ui.input(|i| i.pointer.delta().x) as f64;
-                let range = param.max - param.min;
-                
-                let value_delta = (delta_x / rect.width() as f64) * range;
-                let mut new_value = param.value + value_delta;
-                new_value = new_value.clamp(param.min, param.max);
+            response
+        }).inner
+    }
+}
 
-                if param.step > 0.0 {
-                    new_value = (new_value / param.step).round() * param.step;
-                    new_value = new_value.clamp(param.min, param.max);
-                }
-                
-                if (param.value - new_value).abs() > f64::EPSILON * new_value.abs().max(1.0) {
-                    param.value = new_value;
-                    response.mark_changed();
+// 2つのハンドルで範囲 [lo, hi] を選ぶスライダ
+struct CustomRangeSlider<'a> {
+    param: &'a mut RangeSliderParam,
+    label: Option<String>, // 表示名 (省略時は変数名)
+}
+
+impl<'a> CustomRangeSlider<'a> {
+    fn new(param: &'a mut RangeSliderParam) -> Self {
+        Self { param, label: None }
+    }
+
+    fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+}
+
+impl<'a> Widget for CustomRangeSlider<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let param = self.param;
+        let label = self.label.unwrap_or_else(|| param.name.clone());
+        let id = ui.make_persistent_id(&param.name);
+
+        ui.vertical(|ui| {
+            let decimals = step_decimals(param.step);
+            ui.label(format!("{}: [{:.*}, {:.*}]", label, decimals, param.value[0], decimals, param.value[1]));
+
+            let desired_size = egui::vec2(ui.available_width(), 20.0);
+            let (rect, mut response) = ui.allocate_exact_size(desired_size, Sense::drag());
+
+            let range = param.max - param.min;
+            let x_of = |value: f64| -> f32 {
+                let t = if range > 0.0 { ((value - param.min) / range).clamp(0.0, 1.0) } else { 0.0 };
+                rect.left() + (t * rect.width() as f64) as f32
+            };
+            let line_y = rect.center().y;
+
+            // 操作中のハンドル (0: 下端, 1: 上端) はドラッグ開始時に決めて保持する
+            let active_id = id.with("active_handle");
+            if response.drag_started()
+                && let Some(pos) = response.interact_pointer_pos()
+            {
+                let d0 = (pos.x - x_of(param.value[0])).abs();
+                let d1 = (pos.x - x_of(param.value[1])).abs();
+                // 2つのハンドルが重なっている場合は、押した位置の側のハンドルを動かす
+                let handle: usize = if d0 < d1 || (d0 == d1 && pos.x < x_of(param.value[0])) { 0 } else { 1 };
+                ui.data_mut(|d| d.insert_temp(active_id, handle));
+            }
+            if response.dragged() {
+                let handle: usize = ui.data(|d| d.get_temp(active_id)).unwrap_or(0);
+                if let Some(pos) = response.interact_pointer_pos() {
+                    let t = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0) as f64;
+                    let mut new_value = param.min + t * range;
+                    if param.step > 0.0 {
+                        new_value = (new_value / param.step).round() * param.step;
+                    }
+                    // 下端が上端を追い越さないようにする
+                    new_value = if handle == 0 {
+                        new_value.clamp(param.min, param.value[1])
+                    } else {
+                        new_value.clamp(param.value[0], param.max)
+                    };
+                    if param.value[handle] != new_value {
+                        param.value[handle] = new_value;
+                        response.mark_changed();
+                    }
                 }
             }
 
+            // スライダの「棒」と選択範囲を描画
+            let line_color = ui.visuals().widgets.inactive.bg_fill;
+            ui.painter().line_segment(
+                [Pos2::new(rect.left(), line_y), Pos2::new(rect.right(), line_y)],
+                Stroke::new(2.0, line_color),
+            );
+            let lo_pos = Pos2::new(x_of(param.value[0]), line_y);
+            let hi_pos = Pos2::new(x_of(param.value[1]), line_y);
+            ui.painter().line_segment([lo_pos, hi_pos], Stroke::new(4.0, ui.visuals().selection.bg_fill));
+
+            // ハンドルを描画
+            let handle_color = if response.dragged() {
+                ui.visuals().widgets.active.bg_fill
+            } else {
+                ui.visuals().widgets.inactive.bg_fill
+            };
+            let border_color = if response.dragged() || response.hovered() {
+                ui.visuals().widgets.active.fg_stroke.color
+            } else {
+                ui.visuals().widgets.inactive.fg_stroke.color
+            };
+            for pos in [lo_pos, hi_pos] {
+                ui.painter().circle_filled(pos, 8.5, border_color);
+                ui.painter().circle_filled(pos, 8.0, handle_color);
+            }
+
             response
         }).inner
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // スクリプトを読み込んでsetup()まで実行したアプリ
+    fn app_with_script(code: &str) -> ParametricPlotApp {
+        let mut app = ParametricPlotApp { js_code: code.to_string(), ..ParametricPlotApp::headless() };
+        app.evaluate_script();
+        app
+    }
+
+    fn history_state(code: &str, a: f64) -> HistoryState {
+        HistoryState { code: code.to_string(), values: BTreeMap::from([("a".to_string(), ControlValue::Number(a))]) }
+    }
+
+    #[test]
+    fn history_coalesces_quick_changes_of_the_same_kind() {
+        let mut history = History::default();
+        history.record("初期状態".to_string(), history_state("", 0.0), 0.0);
+        history.record("a".to_string(), history_state("", 1.0), 10.0);
+        history.record("a".to_string(), history_state("", 2.0), 10.5);
+        history.record("a".to_string(), history_state("", 3.0), 12.0);
+        assert_eq!(history.entries.len(), 3);
+        assert_eq!(history.current_state(), Some(&history_state("", 3.0)));
+        assert_eq!(history.entries[1].state, history_state("", 2.0));
+    }
+
+    #[test]
+    fn history_starts_a_new_entry_after_undo() {
+        let mut history = History::default();
+        history.record("初期状態".to_string(), history_state("", 0.0), 0.0);
+        history.record("a".to_string(), history_state("", 1.0), 10.0);
+        history.record("a".to_string(), history_state("", 2.0), 20.0);
+        // 元に戻した直後の変更は、戻した先の項目にまとめずに新しい項目にする
+        assert_eq!(history.go_to(1), Some(&history_state("", 1.0)));
+        assert!(history.can_redo());
+        history.record("a".to_string(), history_state("", 5.0), 20.2);
+        assert_eq!(history.entries.len(), 3);
+        assert_eq!(history.entries[1].state, history_state("", 1.0));
+        assert_eq!(history.current_state(), Some(&history_state("", 5.0)));
+        assert!(!history.can_redo());
+        // その後の変更は通常どおりまとめる
+        history.record("a".to_string(), history_state("", 6.0), 20.4);
+        assert_eq!(history.entries.len(), 3);
+        assert!(history.go_to(0).is_some());
+        assert!(history.go_to(5).is_none());
+        assert_eq!(history.current, 0);
+    }
+
+    #[test]
+    fn history_drops_the_oldest_entries() {
+        let mut history = History::default();
+        for i in 0..MAX_HISTORY_ENTRIES + 10 {
+            history.record(format!("{}", i), history_state("", i as f64), i as f64);
+        }
+        assert_eq!(history.entries.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(history.current, MAX_HISTORY_ENTRIES - 1);
+        assert_eq!(history.entries[0].label, "10");
+    }
+
+    #[test]
+    fn code_edits_are_recorded_while_a_slider_plays() {
+        let mut app = app_with_script("function setup() { addSlider('a', {min: 0, max: 1, animate: true}); }");
+        app.record_state(0.0);
+        app.sliders[0].value = 0.5;
+        app.record_state(1.0);
+        assert_eq!(app.history.entries.len(), 1); // 再生による変化は記録しない
+        app.js_code.push_str("\n// edit");
+        app.record_state(2.0);
+        assert_eq!(app.history.entries.len(), 2);
+        assert_eq!(app.history.entries[1].state.values["a"], ControlValue::Number(0.0));
+        app.sliders[0].playback.playing = false;
+        app.record_state(5.0);
+        assert_eq!(app.history.entries.len(), 3);
+        assert_eq!(app.history.entries[2].label, "パラメータ変更: a");
+    }
+
+    fn slider(min: f64, max: f64, step: f64, scale: SliderScale, integer: bool) -> SliderParam {
+        SliderParam {
+            name: "a".to_string(),
+            min,
+            max,
+            step,
+            value: min,
+            scale,
+            integer,
+            playback: SliderPlayback { playing: true, mode: PlayMode::Loop, speed: 1.0, direction: 1.0 },
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn slider_quantizes_to_step_and_range() {
+        let linear = slider(0.0, 1.0, 0.25, SliderScale::Linear, false);
+        assert_eq!(linear.quantize(0.3), 0.25);
+        assert_eq!(linear.quantize(0.4), 0.5);
+        assert_eq!(linear.quantize(-3.0), 0.0);
+        assert_eq!(linear.quantize(7.0), 1.0);
+        let integer = slider(-5.0, 5.0, 0.001, SliderScale::Linear, true);
+        assert_eq!(integer.quantize(2.6), 3.0);
+        // 対数目盛りではstepで丸めない
+        let log = slider(0.1, 100.0, 1.0, SliderScale::Log, false);
+        assert_eq!(log.quantize(0.37), 0.37);
+    }
+
+    #[test]
+    fn slider_position_round_trips() {
+        let linear = slider(-2.0, 2.0, 0.1, SliderScale::Linear, false);
+        assert_close(linear.to_normalized(1.0), 0.75);
+        assert_close(linear.normalized_to_value(0.25), -1.0);
+        let log = slider(0.1, 1000.0, 0.0, SliderScale::Log, false);
+        assert_close(log.to_normalized(10.0), 0.5);
+        assert_close(log.normalized_to_value(0.25), 1.0);
+        for t in [0.0, 0.3, 0.8, 1.0] {
+            assert_close(log.to_normalized(log.normalized_to_value(t)), t);
+        }
+        // 範囲外やNaNは端に寄せる
+        assert_eq!(linear.to_normalized(10.0), 1.0);
+        assert_eq!(log.to_normalized(-1.0), 0.0);
+        assert_eq!(slider(1.0, 1.0, 0.1, SliderScale::Linear, false).to_normalized(1.0), 0.0);
+    }
+
+    #[test]
+    fn slider_nudges_by_step() {
+        let linear = slider(0.0, 1.0, 0.1, SliderScale::Linear, false);
+        assert_close(linear.nudge(0.5, 1.0), 0.6);
+        assert_close(linear.nudge(0.5, -10.0), 0.0);
+        assert_close(slider(0.0, 10.0, 0.1, SliderScale::Linear, true).nudge(3.0, 1.0), 4.0);
+        let log = slider(1.0, 100.0, 0.0, SliderScale::Log, false);
+        assert_close(log.nudge(1.0, 50.0), 10.0);
+    }
+
+    #[test]
+    fn slider_formats_values_with_step_decimals() {
+        assert_eq!(slider(0.0, 1.0, 0.05, SliderScale::Linear, false).format_value(0.5), "0.50");
+        assert_eq!(slider(0.0, 10.0, 0.5, SliderScale::Linear, true).format_value(3.0), "3");
+        assert_eq!(slider(0.001, 1000.0, 0.0, SliderScale::Log, false).format_value(12.345678), "12.35");
+        assert_eq!(step_decimals(0.001), 3);
+        assert_eq!(step_decimals(2.0), 0);
+        assert_eq!(step_decimals(0.0), 3);
+    }
+
+    #[test]
+    fn slider_playback_modes() {
+        let mut looping = slider(0.0, 1.0, 0.001, SliderScale::Linear, false);
+        looping.value = 0.75;
+        looping.advance_playback(0.5);
+        assert_close(looping.value, 0.25);
+        assert!(looping.playback.playing);
+
+        let mut once = slider(0.0, 1.0, 0.001, SliderScale::Linear, false);
+        once.playback.mode = PlayMode::Once;
+        once.value = 0.75;
+        once.advance_playback(0.5);
+        assert_eq!(once.value, 1.0);
+        assert!(!once.playback.playing);
+
+        let mut bounce = slider(0.0, 1.0, 0.001, SliderScale::Linear, false);
+        bounce.playback.mode = PlayMode::Bounce;
+        bounce.value = 0.75;
+        bounce.advance_playback(0.5);
+        assert_close(bounce.value, 0.75);
+        assert_eq!(bounce.playback.direction, -1.0);
+        bounce.advance_playback(1.0);
+        assert_close(bounce.value, 0.25);
+        assert_eq!(bounce.playback.direction, 1.0);
+
+        // 範囲が0なら再生を止める
+        let mut empty = slider(1.0, 1.0, 0.001, SliderScale::Linear, false);
+        empty.advance_playback(0.5);
+        assert!(!empty.playback.playing);
+    }
+
+    #[test]
+    fn slider_animate_option_starts_playback() {
+        let app = app_with_script("function setup() { addSlider('a', {min: 0, max: 10, animate: 'bounce'}); addSlider('b', {animate: {mode: 'once', speed: 3}}); addSlider('c', {}); }");
+        let playback: Vec<(bool, PlayMode, f64)> = app.sliders.iter().map(|s| (s.playback.playing, s.playback.mode, s.playback.speed)).collect();
+        assert_eq!(playback, [(true, PlayMode::Bounce, 2.0), (true, PlayMode::Once, 3.0), (false, PlayMode::Loop, 0.2)]);
+        let app = app_with_script("function setup() { addSlider('a', {animate: 'sideways'}); }");
+        assert!(app.sliders.is_empty());
+        assert!(app.script_errors().iter().any(|e| e.contains("Unknown animate mode")));
+    }
+
+    // 以前の形式のクエリの値 (base64)
+    fn legacy(text: &str) -> String {
+        use base64::Engine as _;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(text)
+    }
+
+    fn shared_state() -> SharedState {
+        SharedState {
+            code: Some("function draw() {}".to_string()),
+            values: Some(BTreeMap::from([("a".to_string(), ControlValue::Number(0.5)), ("c".to_string(), ControlValue::Color([255, 0, 0]))])),
+            view: Some([-1.0, -2.0, 3.0, 4.0]),
+            preset: Some("p".to_string()),
+            presets: vec![Preset { name: "q".to_string(), values: BTreeMap::from([("a".to_string(), ControlValue::Number(1.0))]), from_code: false }],
+        }
+    }
+
+    fn assert_same_state(a: &SharedState, b: &SharedState) {
+        assert_eq!((&a.code, &a.values, a.view, &a.preset), (&b.code, &b.values, b.view, &b.preset));
+        let presets = |state: &SharedState| state.presets.iter().map(|p| (p.name.clone(), p.values.clone())).collect::<Vec<_>>();
+        assert_eq!(presets(a), presets(b));
+    }
+
+    #[test]
+    fn shared_state_round_trips_through_the_query() {
+        let query = shared_state().to_query("?lang=ja&code=old&view=0,0,1,1");
+        // 関係のないパラメータは残し、以前の形式のパラメータは消す
+        assert!(query.starts_with("lang=ja&s=v1."), "{}", query);
+        assert!(!query.contains("code=") && !query.contains("view="), "{}", query);
+        assert_same_state(&SharedState::from_query(&query).unwrap(), &shared_state());
+        assert_same_state(&SharedState::from_query(&format!("?{}", query)).unwrap(), &shared_state());
+    }
+
+    #[test]
+    fn shared_state_reads_legacy_queries() {
+        let query = format!("code={}&values={}&view=-1,-2,3,4&preset=p", legacy("function draw() {}"), legacy(r#"{"a": 0.5, "c": [255, 0, 0]}"#));
+        let state = SharedState::from_query(&query).unwrap();
+        assert_same_state(&state, &SharedState { presets: Vec::new(), ..shared_state() });
+
+        // 読めない項目は無視する
+        let query = format!("code={}&values={}&view=1,0,0,1", legacy("  "), legacy("not json"));
+        let state = SharedState::from_query(&query).unwrap();
+        assert_eq!((state.code, state.values, state.view), (None, None, None));
+        assert!(SharedState::from_query("s=v1.broken").is_err());
+    }
+
+    #[test]
+    fn shared_state_reads_links_in_any_form() {
+        let link = shared_state().to_link();
+        assert!(link.starts_with(share::SHARE_BASE_URL), "{}", link);
+        let token = share::encode(&shared_state());
+        for text in [link.clone(), format!("  {}#section ", link), format!("s={}", token), token] {
+            assert_same_state(&SharedState::from_link(&text).unwrap(), &shared_state());
+        }
+        assert!(SharedState::from_link("https://example.com/").is_err());
+    }
+
+    #[test]
+    fn document_round_trips_through_json() {
+        let mut app = app_with_script("function setup() { addSlider('a', {min: 0, max: 1, step: 0.1}); addCheckbox('b', 'B'); }");
+        app.apply_control_values(&BTreeMap::from([("a".to_string(), ControlValue::Number(0.3)), ("b".to_string(), ControlValue::Bool(true))]));
+        app.metadata.title = "タイトル".to_string();
+        app.plot_options.data_aspect = None;
+        app.plot_bounds = Some([-1.0, -2.0, 3.0, 4.0]);
+        let json = app.to_document().to_json();
+
+        let mut restored = ParametricPlotApp::headless();
+        restored.load_document(GraphDocument::from_json(&json).unwrap());
+        restored.evaluate_script();
+        assert_eq!(restored.js_code, app.js_code);
+        assert_eq!(restored.control_values(), app.control_values());
+        assert_eq!(restored.metadata.title, "タイトル");
+        assert_eq!(restored.plot_options.data_aspect, None);
+        assert_eq!(restored.pending_plot_bounds, Some([-1.0, -2.0, 3.0, 4.0]));
+    }
+
+    #[test]
+    fn document_fields_other_than_the_script_are_optional() {
+        let document = GraphDocument::from_json(r#"{"format": "graphapp", "version": 1, "script": "function setup() {}"}"#).unwrap();
+        assert_eq!(document.script, "function setup() {}");
+        assert!(document.values.is_empty() && document.presets.is_empty() && document.view.is_none());
+        assert!(document.plot.show_axes && document.plot.show_grid);
+        assert_eq!(document.metadata.title, "");
+    }
+
+    #[test]
+    fn document_rejects_other_files() {
+        let error = |json: &str| GraphDocument::from_json(json).err().unwrap();
+        assert!(error(r#"{"format": "other", "version": 1, "script": ""}"#).starts_with("グラフのドキュメントではありません"));
+        assert!(error(r#"{"format": "graphapp", "version": 2, "script": ""}"#).starts_with("新しいバージョン"));
+        assert!(error(r#"{"format": "graphapp", "version": 1}"#).starts_with("ドキュメントを読み込めません"));
+        assert!(error("function setup() {}").starts_with("ドキュメントを読み込めません"));
+    }
+
+    #[test]
+    fn range_slider_orders_and_clamps_values() {
+        let range = RangeSliderParam { name: "r".to_string(), min: 0.0, max: 10.0, step: 0.1, value: [0.0, 10.0] };
+        assert_eq!(range.clamp_range([8.0, 2.0]), [2.0, 8.0]);
+        assert_eq!(range.clamp_range([-5.0, 20.0]), [0.0, 10.0]);
+    }
+
+    #[test]
+    fn range_slider_rejects_invalid_bounds() {
+        for params in ["{min: 5, max: 1}", "{min: NaN, max: 1}", "{min: 0, max: NaN}"] {
+            let app = app_with_script(&format!("function setup() {{ addRangeSlider('r', {}); }}", params));
+            assert!(app.range_sliders.is_empty(), "{}", params);
+            assert!(app.script_errors().iter().any(|e| e.contains("min must be less than or equal to max")), "{}", params);
+        }
+    }
+
+    #[test]
+    fn range_slider_applies_inverted_values_in_order() {
+        let mut app = app_with_script("function setup() { addRangeSlider('r', {min: 0, max: 1, default: [0.8, 0.2]}); }");
+        assert_eq!(app.range_sliders[0].value, [0.2, 0.8]);
+        app.apply_control_values(&BTreeMap::from([("r".to_string(), ControlValue::Pair([5.0, -1.0]))]));
+        assert_eq!(app.range_sliders[0].value, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn color_preset_round_trips() {
+        let mut app = app_with_script("function setup() { addColorpicker('c', [0, 0, 0]); addPreset('red', {c: [255, 0, 0]}); }");
+        assert!(app.script_errors().is_empty(), "{:?}", app.script_errors());
+        assert_eq!(app.presets[0].values["c"], ControlValue::Color([255, 0, 0]));
+        app.animate_presets = false;
+        app.apply_preset("red");
+        assert_eq!(app.control_values()["c"], ControlValue::Color([255, 0, 0]));
+        // 保存した形式からも同じ値として読める
+        let json = serde_json::to_string(&app.presets).unwrap();
+        let restored: Vec<Preset> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored[0].values, app.presets[0].values);
+    }
+
+    #[test]
+    fn slider_rejects_invalid_bounds() {
+        let app = app_with_script("function setup() { addSlider('a', {min: 1, max: 0}); }");
+        assert!(app.sliders.is_empty());
+        assert!(!app.script_errors().is_empty());
+    }
+
+    #[test]
+    fn command_line_values_are_parsed_as_the_control_kind() {
+        let parse = ControlValue::parse_arg;
+        assert_eq!(parse("2.5", &ControlValue::Number(0.0)), Ok(ControlValue::Number(2.5)));
+        assert_eq!(parse("false", &ControlValue::Bool(true)), Ok(ControlValue::Bool(false)));
+        assert_eq!(parse("1, -2", &ControlValue::Pair([0.0, 0.0])), Ok(ControlValue::Pair([1.0, -2.0])));
+        assert_eq!(parse("#ff8000", &ControlValue::Color([0, 0, 0])), Ok(ControlValue::Color([255, 128, 0])));
+        assert_eq!(parse("0,128,255", &ControlValue::Color([0, 0, 0])), Ok(ControlValue::Color([0, 128, 255])));
+        assert_eq!(parse("a,b", &ControlValue::Text(String::new())), Ok(ControlValue::Text("a,b".to_string())));
+        assert!(parse("abc", &ControlValue::Number(0.0)).is_err());
+        assert!(parse("inf", &ControlValue::Number(0.0)).is_err());
+        assert!(parse("yes", &ControlValue::Bool(false)).is_err());
+        assert!(parse("1", &ControlValue::Pair([0.0, 0.0])).is_err());
+        assert!(parse("256,0,0", &ControlValue::Color([0, 0, 0])).is_err());
+        assert!(parse("#fff", &ControlValue::Color([0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn headless_run_reports_load_errors() {
+        let mut app = ParametricPlotApp { js_code: "function setup() {".to_string(), ..ParametricPlotApp::headless() };
+        let error = app.prepare_headless(&[]).err().unwrap();
+        assert!(error.contains("Load error"), "{}", error);
+    }
+
+    #[test]
+    fn headless_overrides_are_checked() {
+        let script = "function setup() { addSlider('a', {min: 0, max: 1, step: 0.1, default: 0.5}); } function draw() {}";
+        let mut app = app_with_script(script);
+        assert!(app.prepare_headless(&[("a".to_string(), "0.2".to_string())]).is_ok());
+        assert_eq!(app.sliders[0].value, 0.2);
+        assert!(app.prepare_headless(&[("a".to_string(), "2".to_string())]).is_err());
+        assert!(app.prepare_headless(&[("b".to_string(), "0".to_string())]).is_err());
+    }
+
+    #[test]
+    fn sweep_values_follow_the_slider_step() {
+        let script = "function setup() { addSlider('a', {min: 0, max: 1, step: 0.25, default: 0}); } function draw() { addText('a', [a, 0]); }";
+        let mut app = app_with_script(script);
+        let recording = Recording { source: RecordSource::Sweep { name: "a".to_string(), from: 0.0, to: 1.0 }, frames: 7, fps: 30.0 };
+        let figures = app.record_animation(&recording, None).unwrap();
+        let values: Vec<f64> = figures.iter().map(|figure| figure.scene.items[0].points()[0][0]).collect();
+        assert_eq!(values, [0.0, 0.25, 0.25, 0.5, 0.75, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn animation_rejects_invalid_bounds() {
+        for params in ["{min: 5, max: 1}", "{min: 0, max: NaN}"] {
+            let app = app_with_script(&format!("function setup() {{ addAnimation('t', {}); }}", params));
+            assert!(app.animations.is_empty(), "{}", params);
+            assert!(app.script_errors().iter().any(|e| e.contains("min must be less than or equal to max")), "{}", params);
+        }
+    }
+
+    #[test]
+    fn animation_stays_within_bounds() {
+        let mut app = app_with_script("function setup() { addAnimation('t', {min: 1, max: 2, speed: 3}); addAnimation('u', {min: 0, max: 1, loop: false}); addAnimation('v', {min: 1, max: 1}); }");
+        for animation in &mut app.animations {
+            animation.advance(0.5);
+        }
+        let values: Vec<f64> = app.animations.iter().map(|a| a.value).collect();
+        assert_eq!(values, [1.5, 0.5, 1.0]);
+        for animation in &mut app.animations {
+            animation.advance(1.0);
+        }
+        let values: Vec<f64> = app.animations.iter().map(|a| a.value).collect();
+        assert_eq!(values, [1.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn text_labels_are_added_to_the_scene() {
+        let mut app = app_with_script("function setup() {} function draw() { addText('P', [1, 2], { color: [255, 0, 0], size: 20 }); addText('Q', [0, 0]); }");
+        app.run_draw();
+        assert!(app.script_errors().is_empty(), "{:?}", app.script_errors());
+        assert_eq!(app.scene().items, vec![
+            SceneItem::Text { text: "P".to_string(), position: [1.0, 2.0], style: TextStyle { color: Color::rgb(255, 0, 0), size: 20.0 } },
+            SceneItem::Text { text: "Q".to_string(), position: [0.0, 0.0], style: TextStyle { color: Color::rgb(0, 0, 0), size: 14.0 } },
+        ]);
+    }
+}
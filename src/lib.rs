@@ -1,10 +1,26 @@
-#[cfg(target_arch = "wasm32")]
-use wasm_bindgen::prelude::*;
-
+#[cfg(not(target_arch = "wasm32"))]
+mod export;
 mod graph;
+mod native;
+mod plot;
+mod plotter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod render;
+pub mod scene;
+mod script;
+mod share;
 
-use eframe::egui;
+// 他のeguiアプリに埋め込むためのAPI
+pub use graph::ControlValue;
+pub use native::{NativeArgs, NativeFn, NativeModule, NativeValue};
+pub use plotter::GraphPlotter;
+pub use script::{BoaEngine, HostFn, ScriptCaller, ScriptEngine, ScriptFunction};
 
+// アプリ本体 (ネイティブ版はmain.rsから使う)
+pub use graph::{ParametricPlotApp, setup_logging};
+
+#[cfg(target_arch = "wasm32")]
+use eframe::egui;
 #[cfg(target_arch = "wasm32")]
 use eframe::wasm_bindgen::{self, prelude::*};
 
@@ -47,7 +63,7 @@ pub async fn start(canvas_id: &str) -> Result<(), JsValue> {
                     prop.insert(0, "NotoSerifJP".to_owned());
                 }
                 cc.egui_ctx.set_fonts(fonts);
-                Ok(Box::new(graph::ParametricPlotApp::default()))
+                Ok(Box::new(graph::ParametricPlotApp::new(cc)))
                 // Ok(Box::new(app::MyApp::default()))
             }),
         )
@@ -1,14 +1,38 @@
 #![cfg(not(target_arch = "wasm32"))]
 
-mod graph;
-
 use eframe::egui;
-use graph::{ParametricPlotApp, setup_logging};
+use graphapp::{ParametricPlotApp, render, setup_logging};
+use std::path::PathBuf;
 
 fn main() -> eframe::Result<()> {
     // ログ設定を初期化
     setup_logging();
 
+    // コマンドライン引数
+    //   graphapp path/to/file.graph.json   ドキュメントファイルを開く
+    //   graphapp --watch path/to/script.js スクリプトファイルを読み込み、変更されるたびに再評価する
+    //   graphapp render script.js -o out.svg ウィンドウを開かずに図を書き出す (.svg / .png / .tex)
+    let args: Vec<_> = std::env::args_os().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "render") {
+        std::process::exit(render::run(&args[1..]));
+    }
+    let mut document_path = None;
+    let mut watch_path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--watch" {
+            match args.next() {
+                Some(path) => watch_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--watch には監視するスクリプトファイルを指定してください");
+                    std::process::exit(2);
+                }
+            }
+        } else {
+            document_path = Some(PathBuf::from(arg));
+        }
+    }
+
     // アプリケーションオプションの設定
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -21,7 +45,7 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Neknaj Graph Plotter",
         native_options,
-        Box::new(|cc| {
+        Box::new(move |cc| {
             // フォント定義をカスタマイズ
             let mut fonts = egui::FontDefinitions::default();
             fonts.font_data.insert(
@@ -39,7 +63,16 @@ fn main() -> eframe::Result<()> {
                 prop.insert(0, "NotoSerifJP".to_owned());
             }
             cc.egui_ctx.set_fonts(fonts);
-            Ok(Box::new(ParametricPlotApp::default()))
+            let mut app = ParametricPlotApp::new(cc);
+            if let Some(path) = &document_path
+                && let Err(e) = app.open_document(path)
+            {
+                eprintln!("{}", e);
+            }
+            if let Some(path) = &watch_path {
+                app.watch_script(path.clone());
+            }
+            Ok(Box::new(app))
         }),
     )
 }
\ No newline at end of file
@@ -0,0 +1,365 @@
+// Rustで書いた関数をスクリプトから呼び出せるようにする拡張
+//
+//   plotter.register_function("besselJ", |args| {
+//       let n = args.first().and_then(NativeValue::as_f64).ok_or("besselJ(n, x): nを指定してください")?;
+//       let x = args.get(1).and_then(NativeValue::as_f64).ok_or("besselJ(n, x): xを指定してください")?;
+//       Ok(bessel_j(n, x))
+//   });
+//   plotter.register_module(NativeModule::new("stats").function("mean", |args| ...));  // stats.mean(...)
+//
+// 引数と戻り値は数値・真偽値・文字列・配列 ([x, y] の点を含む)・オブジェクトを自動で変換する。
+// 関数がErrを返した場合はスクリプト側で例外になる。
+
+use crate::script::{HostFn, ScriptCaller, ScriptEngine, ScriptFunction};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+// スクリプトとRustの間でやり取りする値
+#[derive(Clone, Debug, PartialEq)]
+pub enum NativeValue {
+    Undefined, // undefined と null
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<NativeValue>),
+    Object(BTreeMap<String, NativeValue>),
+    Function(ScriptFunction), // スクリプトで定義された関数 (ScriptCallerで呼び出す)
+}
+
+const UNDEFINED: NativeValue = NativeValue::Undefined;
+
+impl NativeValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            NativeValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            NativeValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            NativeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[NativeValue]> {
+        match self {
+            NativeValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    // [x, y] の形の配列を点として読む (3つ目以降の要素は無視する)
+    pub fn as_point(&self) -> Option<[f64; 2]> {
+        match self.as_array()? {
+            [x, y, ..] => Some([x.as_f64()?, y.as_f64()?]),
+            _ => None,
+        }
+    }
+
+    // 数値の配列を読む
+    pub fn as_numbers(&self) -> Option<Vec<f64>> {
+        self.as_array()?.iter().map(NativeValue::as_f64).collect()
+    }
+
+    // 点の配列を読む
+    pub fn as_points(&self) -> Option<Vec<[f64; 2]>> {
+        self.as_array()?.iter().map(NativeValue::as_point).collect()
+    }
+
+    // オブジェクトのプロパティ (なければUndefined)
+    pub fn get(&self, key: &str) -> &NativeValue {
+        match self {
+            NativeValue::Object(fields) => fields.get(key).unwrap_or(&UNDEFINED),
+            _ => &UNDEFINED,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&ScriptFunction> {
+        match self {
+            NativeValue::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        matches!(self, NativeValue::Undefined)
+    }
+
+    // 条件として評価したときに真になるか (JavaScriptと同じ規則)
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            NativeValue::Undefined => false,
+            NativeValue::Bool(b) => *b,
+            NativeValue::Number(n) => *n != 0.0 && !n.is_nan(),
+            NativeValue::String(s) => !s.is_empty(),
+            NativeValue::Array(_) | NativeValue::Object(_) | NativeValue::Function(_) => true,
+        }
+    }
+
+    // JSONに変換する (関数はnullになる)
+    // 整数値の数はJSON.stringifyと同じく整数として書く (色の [r, g, b] などを整数として読めるようにする)
+    pub fn to_json(&self) -> serde_json::Value {
+        const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+        match self {
+            NativeValue::Undefined | NativeValue::Function(_) => serde_json::Value::Null,
+            NativeValue::Bool(b) => serde_json::Value::Bool(*b),
+            NativeValue::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER => serde_json::Value::from(*n as i64),
+            NativeValue::Number(n) => serde_json::Number::from_f64(*n).map_or(serde_json::Value::Null, serde_json::Value::Number),
+            NativeValue::String(s) => serde_json::Value::String(s.clone()),
+            NativeValue::Array(items) => serde_json::Value::Array(items.iter().map(NativeValue::to_json).collect()),
+            NativeValue::Object(fields) => serde_json::Value::Object(fields.iter().map(|(key, field)| (key.clone(), field.to_json())).collect()),
+        }
+    }
+}
+
+// 文字列に変換したときの表記 (JavaScriptのString(value)と同じ)
+impl fmt::Display for NativeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NativeValue::Undefined => f.write_str("undefined"),
+            NativeValue::Bool(b) => write!(f, "{}", b),
+            NativeValue::Number(n) if n.is_infinite() => f.write_str(if *n > 0.0 { "Infinity" } else { "-Infinity" }),
+            NativeValue::Number(n) => write!(f, "{}", n),
+            NativeValue::String(s) => f.write_str(s),
+            NativeValue::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    if !item.is_undefined() {
+                        write!(f, "{}", item)?;
+                    }
+                }
+                Ok(())
+            }
+            NativeValue::Object(_) => f.write_str("[object Object]"),
+            NativeValue::Function(_) => f.write_str("function"),
+        }
+    }
+}
+
+// 引数の一覧から値を取り出す (足りない引数はUndefined)
+pub trait NativeArgs {
+    fn get_or_undefined(&self, index: usize) -> &NativeValue;
+}
+
+impl NativeArgs for [NativeValue] {
+    fn get_or_undefined(&self, index: usize) -> &NativeValue {
+        self.get(index).unwrap_or(&UNDEFINED)
+    }
+}
+
+impl From<()> for NativeValue {
+    fn from(_: ()) -> Self {
+        NativeValue::Undefined
+    }
+}
+
+impl From<bool> for NativeValue {
+    fn from(value: bool) -> Self {
+        NativeValue::Bool(value)
+    }
+}
+
+impl From<f64> for NativeValue {
+    fn from(value: f64) -> Self {
+        NativeValue::Number(value)
+    }
+}
+
+impl From<&str> for NativeValue {
+    fn from(value: &str) -> Self {
+        NativeValue::String(value.to_string())
+    }
+}
+
+impl From<String> for NativeValue {
+    fn from(value: String) -> Self {
+        NativeValue::String(value)
+    }
+}
+
+impl From<[f64; 2]> for NativeValue {
+    fn from(value: [f64; 2]) -> Self {
+        NativeValue::Array(vec![value[0].into(), value[1].into()])
+    }
+}
+
+impl<T: Into<NativeValue>> From<Vec<T>> for NativeValue {
+    fn from(values: Vec<T>) -> Self {
+        NativeValue::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+// 登録された関数
+pub type NativeFn = Rc<dyn Fn(&[NativeValue]) -> Result<NativeValue, String>>;
+
+// 戻り値をNativeValueに変換する関数にする
+fn native_fn<R: Into<NativeValue>>(f: impl Fn(&[NativeValue]) -> Result<R, String> + 'static) -> NativeFn {
+    Rc::new(move |args: &[NativeValue]| f(args).map(Into::into))
+}
+
+// 関数をまとめたオブジェクト (スクリプトからは name.function(...) で呼び出す)
+#[derive(Clone)]
+pub struct NativeModule {
+    name: String,
+    functions: Vec<(String, NativeFn)>,
+}
+
+impl NativeModule {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), functions: Vec::new() }
+    }
+
+    pub fn function<R: Into<NativeValue>>(mut self, name: impl Into<String>, f: impl Fn(&[NativeValue]) -> Result<R, String> + 'static) -> Self {
+        let name = name.into();
+        self.functions.retain(|(n, _)| *n != name);
+        self.functions.push((name, native_fn(f)));
+        self
+    }
+}
+
+// 登録された関数とモジュールの一覧
+// スクリプトを読み込み直すたびに実行環境を作り直すため、ここに保持しておいて毎回登録する
+#[derive(Clone, Default)]
+pub struct NativeRegistry {
+    functions: Vec<(String, NativeFn)>,
+    modules: Vec<NativeModule>,
+}
+
+impl NativeRegistry {
+    // 同じ名前の関数がすでにあれば置き換える
+    pub fn register_function<R: Into<NativeValue>>(&mut self, name: impl Into<String>, f: impl Fn(&[NativeValue]) -> Result<R, String> + 'static) {
+        let name = name.into();
+        self.functions.retain(|(n, _)| *n != name);
+        self.functions.push((name, native_fn(f)));
+    }
+
+    // 同じ名前のモジュールがすでにあれば置き換える
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.modules.retain(|m| m.name != module.name);
+        self.modules.push(module);
+    }
+
+    // 実行環境のグローバルに関数とモジュールを登録する
+    pub fn install(&self, engine: &mut dyn ScriptEngine) -> Result<(), String> {
+        for (name, f) in &self.functions {
+            engine.register_function(name, to_host_fn(name, f.clone()))?;
+        }
+        for module in &self.modules {
+            let functions: Vec<(String, HostFn)> = module.functions.iter()
+                .map(|(name, f)| (name.clone(), to_host_fn(name, f.clone())))
+                .collect();
+            engine.register_module(&module.name, &functions)?;
+        }
+        Ok(())
+    }
+}
+
+// エラーに関数名を付けて、実行環境に登録できる形にする
+fn to_host_fn(name: &str, f: NativeFn) -> HostFn {
+    let name = name.to_string();
+    Rc::new(move |_caller: &mut dyn ScriptCaller, args: &[NativeValue]| f(args).map_err(|message| format!("{}: {}", name, message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::BoaEngine;
+
+    #[test]
+    fn integral_numbers_become_json_integers() {
+        let value = NativeValue::from(vec![255.0, 0.0, -3.0]);
+        assert_eq!(value.to_json(), serde_json::json!([255, 0, -3]));
+        assert_eq!(NativeValue::Number(0.5).to_json(), serde_json::json!(0.5));
+        assert_eq!(NativeValue::Number(1e300).to_json(), serde_json::json!(1e300));
+        assert_eq!(NativeValue::Number(f64::NAN).to_json(), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn nested_values_become_json() {
+        let value = NativeValue::Object(BTreeMap::from([
+            ("point".to_string(), NativeValue::from([1.5, -2.0])),
+            ("label".to_string(), NativeValue::from("P")),
+            ("visible".to_string(), NativeValue::from(true)),
+            ("missing".to_string(), NativeValue::Undefined),
+            ("callback".to_string(), NativeValue::Function(ScriptFunction::new(0))),
+        ]));
+        assert_eq!(value.to_json(), serde_json::json!({
+            "point": [1.5, -2], "label": "P", "visible": true, "missing": null, "callback": null
+        }));
+    }
+
+    #[test]
+    fn script_values_round_trip_through_json() {
+        let mut engine = BoaEngine::new();
+        let json = serde_json::json!({"a": [1, 2.5, "x", true, null], "b": {"c": -3}});
+        engine.load(&format!("var value = {};", json)).unwrap();
+        let value = engine.global("value");
+        assert_eq!(value.get("a").as_array().map(<[NativeValue]>::len), Some(5));
+        assert_eq!(value.get("b").get("c"), &NativeValue::Number(-3.0));
+        assert_eq!(value.to_json(), json);
+    }
+
+    #[test]
+    fn display_matches_javascript_string_conversion() {
+        let cases = [
+            (NativeValue::Undefined, "undefined"),
+            (NativeValue::Number(2.0), "2"),
+            (NativeValue::Number(0.25), "0.25"),
+            (NativeValue::Number(f64::NEG_INFINITY), "-Infinity"),
+            (NativeValue::from(vec![NativeValue::Number(1.0), NativeValue::Undefined, NativeValue::from("a")]), "1,,a"),
+            (NativeValue::Object(BTreeMap::new()), "[object Object]"),
+            (NativeValue::Bool(false), "false"),
+        ];
+        for (value, text) in cases {
+            assert_eq!(value.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn accessors_read_points_and_properties() {
+        let points = NativeValue::from(vec![[0.0, 1.0], [2.0, 3.0]]);
+        assert_eq!(points.as_points(), Some(vec![[0.0, 1.0], [2.0, 3.0]]));
+        assert_eq!(NativeValue::from(vec![1.0, 2.0, 3.0]).as_point(), Some([1.0, 2.0]));
+        assert_eq!(NativeValue::from(vec![1.0]).as_point(), None);
+        assert_eq!(NativeValue::from(vec![NativeValue::from(1.0), NativeValue::from("2")]).as_numbers(), None);
+        assert!(NativeValue::Number(1.0).get("x").is_undefined());
+        assert!(!NativeValue::Number(f64::NAN).is_truthy());
+        assert!(NativeValue::Array(Vec::new()).is_truthy());
+        assert!(!NativeValue::from("").is_truthy());
+    }
+
+    #[test]
+    fn registered_functions_are_called_with_converted_values() {
+        let mut registry = NativeRegistry::default();
+        registry.register_function("norm", |args| {
+            let [x, y] = args.first().and_then(NativeValue::as_point).ok_or("点を指定してください")?;
+            Ok(x.hypot(y))
+        });
+        registry.register_function("twice", |args| Ok(args.iter().chain(args).cloned().collect::<Vec<_>>()));
+        // 同じ名前で登録し直すと置き換わる
+        registry.register_function("twice", |args| Ok(vec![args.get_or_undefined(0).clone(); 2]));
+        registry.register_module(NativeModule::new("stats").function("sum", |args| Ok(args.iter().filter_map(NativeValue::as_f64).sum::<f64>())));
+
+        let mut engine = BoaEngine::new();
+        registry.install(&mut engine).unwrap();
+        engine.load("var n = norm([3, 4]); var t = twice('a'); var s = stats.sum(1, 2, 3);").unwrap();
+        assert_eq!(engine.global("n"), NativeValue::Number(5.0));
+        assert_eq!(engine.global("t"), NativeValue::from(vec!["a", "a"]));
+        assert_eq!(engine.global("s"), NativeValue::Number(6.0));
+        // エラーには関数名が付き、スクリプト側では例外になる
+        let error = engine.load("norm(1);").unwrap_err();
+        assert!(error.contains("norm: 点を指定してください"), "{}", error);
+    }
+}
@@ -0,0 +1,48 @@
+// シーンをegui_plotで画面に描く
+
+use crate::scene::{arrow_head, Color, Scene, SceneItem};
+use eframe::egui::{Color32, RichText, Stroke};
+use egui_plot::{Line, PlotPoint, PlotPoints, PlotUi, Polygon, Text};
+
+impl From<Color> for Color32 {
+    fn from(color: Color) -> Self {
+        Color32::from_rgb(color.r(), color.g(), color.b())
+    }
+}
+
+// シーンの図形を追加された順に描く
+pub fn show_scene(plot_ui: &mut PlotUi, scene: &Scene) {
+    for item in &scene.items {
+        match item {
+            SceneItem::Polygon { name, points, style } => {
+                let polygon = Polygon::new(name, PlotPoints::new(points.clone()))
+                    .stroke(Stroke::new(style.weight, Color32::from(style.stroke)));
+                plot_ui.polygon(polygon);
+            }
+            SceneItem::Curve { name, points, style, .. } => {
+                let line = Line::new(name, PlotPoints::new(points.clone()))
+                    .color(Color32::from(style.color))
+                    .width(style.weight);
+                plot_ui.line(line);
+            }
+            SceneItem::Vector { name, origin, tip, style, .. } => {
+                let color = Color32::from(style.color);
+                // 1. ベクトルの本体を描画
+                plot_ui.line(Line::new(format!("{}_main", name), PlotPoints::new(vec![*origin, *tip])).color(color).width(style.weight));
+
+                // ベクトルが非常に短い場合は矢じりを描画しない
+                let Some([arrow_p1, arrow_p2]) = arrow_head(*origin, *tip) else {
+                    continue;
+                };
+                // 2. 矢じりの線1を描画
+                plot_ui.line(Line::new(format!("{}_arrow1", name), PlotPoints::new(vec![*tip, arrow_p1])).color(color).width(style.weight));
+                // 3. 矢じりの線2を描画
+                plot_ui.line(Line::new(format!("{}_arrow2", name), PlotPoints::new(vec![*tip, arrow_p2])).color(color).width(style.weight));
+            }
+            SceneItem::Text { text, position, style } => {
+                let rich_text = RichText::new(text).size(style.size);
+                plot_ui.text(Text::new(text, PlotPoint::from(*position), rich_text).color(Color32::from(style.color)));
+            }
+        }
+    }
+}
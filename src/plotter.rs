@@ -0,0 +1,185 @@
+// 他のeguiアプリに埋め込むためのプロット
+//
+//   let mut plotter = GraphPlotter::new("my_plot");
+//   plotter.set_script(source);
+//   plotter.set_param("radius", 2.0);
+//   plotter.show(ui);  // または ui.add(&mut plotter)
+//
+// スクリプトの setup() で定義したUI要素は画面に表示せず、値は set_param で埋め込む側から指定する。
+// register_function・register_module で登録したRustの関数はスクリプトから呼び出せる (native.rs)。
+
+use crate::graph::{ControlValue, ParametricPlotApp};
+use crate::native::{NativeModule, NativeValue};
+use crate::plot;
+use crate::scene::Scene;
+use crate::script::ScriptEngine;
+use eframe::egui::{self, Response, Ui, Widget};
+use egui_plot::Plot;
+use std::collections::BTreeMap;
+
+pub struct GraphPlotter {
+    app: ParametricPlotApp,
+    id: egui::Id,
+    params: BTreeMap<String, ControlValue>, // set_paramで指定された値 (スクリプトを置き換えても保つ)
+    needs_evaluate: bool, // 次に表示するときにスクリプトを評価し直す
+    needs_draw: bool,     // 次に表示するときにdraw()を実行する
+    show_axes: bool,
+    show_grid: bool,
+    data_aspect: Option<f32>,
+}
+
+impl GraphPlotter {
+    // id_saltは同じ画面に複数のプロットを置く場合に区別するための値
+    pub fn new(id_salt: impl std::hash::Hash) -> Self {
+        Self {
+            app: ParametricPlotApp::headless(),
+            id: egui::Id::new(id_salt),
+            params: BTreeMap::new(),
+            needs_evaluate: true,
+            needs_draw: true,
+            show_axes: true,
+            show_grid: true,
+            data_aspect: Some(1.0),
+        }
+    }
+
+    // スクリプト (setup() と draw() を定義したJavaScript) を置き換える
+    pub fn set_script(&mut self, source: impl Into<String>) {
+        self.app.set_js_code(source.into());
+        self.needs_evaluate = true;
+    }
+
+    // UI要素の値を指定する (スライダ・数値入力なら数値、チェックボックスなら真偽値など)
+    pub fn set_param(&mut self, name: &str, value: impl Into<ControlValue>) {
+        let value = value.into();
+        if self.params.get(name) == Some(&value) {
+            return;
+        }
+        self.params.insert(name.to_string(), value);
+        self.needs_draw = true;
+    }
+
+    // スクリプトで定義されたUI要素の現在の値
+    pub fn params(&mut self) -> BTreeMap<String, ControlValue> {
+        self.update();
+        self.app.control_values()
+    }
+
+    // スクリプトから呼び出せるRustの関数を登録する
+    pub fn register_function<R: Into<NativeValue>>(&mut self, name: impl Into<String>, f: impl Fn(&[NativeValue]) -> Result<R, String> + 'static) {
+        self.app.register_function(name, f);
+        self.needs_evaluate = true;
+    }
+
+    // 関数をまとめたオブジェクトを登録する (スクリプトからは name.function(...) で呼び出す)
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.app.register_module(module);
+        self.needs_evaluate = true;
+    }
+
+    // スクリプトの実行環境を置き換える (既定はBoaEngine)
+    pub fn set_script_engine(&mut self, engine: Box<dyn ScriptEngine>) {
+        self.app.set_script_engine(engine);
+        self.needs_evaluate = true;
+    }
+
+    pub fn show_axes(mut self, show: bool) -> Self {
+        self.show_axes = show;
+        self
+    }
+
+    pub fn show_grid(mut self, show: bool) -> Self {
+        self.show_grid = show;
+        self
+    }
+
+    // x方向とy方向の縮尺の比 (Noneなら揃えない)
+    pub fn data_aspect(mut self, aspect: Option<f32>) -> Self {
+        self.data_aspect = aspect;
+        self
+    }
+
+    // 現在の描画内容
+    pub fn scene(&mut self) -> Scene {
+        self.update();
+        self.app.scene()
+    }
+
+    // スクリプトの実行中に起きたエラー
+    pub fn errors(&self) -> Vec<String> {
+        self.app.script_errors()
+    }
+
+    // 必要ならスクリプトを評価し直し、draw()を実行する
+    fn update(&mut self) {
+        if self.needs_evaluate {
+            self.app.evaluate_script();
+            self.needs_evaluate = false;
+            self.needs_draw = true;
+        }
+        if self.needs_draw {
+            self.app.apply_control_values(&self.params);
+            self.app.run_draw();
+            self.needs_draw = false;
+        }
+    }
+
+    // 使える領域いっぱいにプロットを表示する
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        // アニメーション・シミュレーションを進める
+        let dt = ui.input(|i| i.stable_dt) as f64;
+        if !self.needs_evaluate && self.app.advance_frame(dt) {
+            self.needs_draw = true;
+            ui.ctx().request_repaint();
+        }
+        self.update();
+
+        let mut plot = Plot::new(self.id)
+            .show_axes([self.show_axes, self.show_axes])
+            .show_grid(self.show_grid)
+            .x_axis_label("x")
+            .y_axis_label("y");
+        if let Some(aspect) = self.data_aspect {
+            plot = plot.data_aspect(aspect);
+        }
+        let scene = self.app.scene();
+        plot.show(ui, |plot_ui| plot::show_scene(plot_ui, &scene)).response
+    }
+}
+
+impl Default for GraphPlotter {
+    fn default() -> Self {
+        Self::new("graph_plotter")
+    }
+}
+
+impl Widget for &mut GraphPlotter {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::SceneItem;
+
+    #[test]
+    fn evaluates_a_script_without_a_window() {
+        let mut plotter = GraphPlotter::new("test");
+        plotter.set_script("function setup() { addSlider('r', {min: 0, max: 5, default: 1}); } function draw() { addText('r', [r, 0]); }");
+        plotter.set_param("r", 2.0);
+        let scene = plotter.scene();
+        assert!(plotter.errors().is_empty(), "{:?}", plotter.errors());
+        assert!(matches!(&scene.items[..], [SceneItem::Text { position: [2.0, 0.0], .. }]));
+        assert_eq!(plotter.params()["r"], ControlValue::Number(2.0));
+    }
+
+    #[test]
+    fn script_errors_are_reported() {
+        let mut plotter = GraphPlotter::new("test");
+        plotter.set_script("function setup() {} function draw() { undefinedFunction(); }");
+        let _ = plotter.scene();
+        assert!(!plotter.errors().is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+// ウィンドウを開かずに図を書き出すコマンド
+//
+//   graphapp render script.js -o out.svg [--set name=value]... [--width 800] [--height 600] [--dpi 96] [--view x0,y0,x1,y1]
+//                   [--tex tikz|pgfplots] [--snippet] [--frames 60] [--fps 30] [--sweep name=from:to] [--sequence]
+//
+// 出力形式は出力ファイルの拡張子 (.svg / .png / .tex / .csv / .json) で決まる。--dpi はPNGの線の太さ・文字の大きさと記録する解像度に使う。
+// .tex では --tex で TikZ の図形か PGFPlots の axis 環境かを選び、--snippet を付けると文書に貼り付ける部分だけを書き出す。
+// .csv / .json では画像の代わりに、曲線・ベクトル・多角形の点のデータ (名前・スタイル・t を含む) を書き出す。
+//
+// --frames を指定するとアニメーションとして1コマずつ描画し、.gif ならGIF、.png ならAPNGにする。
+// --sequence を付けると out_0000.png, out_0001.png, ... の連番PNGにする。
+// 各コマでは time を 1/fps 秒ずつ進める。--sweep を指定した場合は代わりにスライダの値を from から to まで動かす。
+//
+// スクリプトの代わりにドキュメントファイル (.graph.json) も指定できる。
+// その場合はドキュメントに保存されたUI要素の値・表示範囲・表示設定を使う。
+
+use crate::export;
+use crate::graph::{ParametricPlotApp, RecordSource, Recording};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const USAGE: &str = "使い方: graphapp render <script.js | document.graph.json> -o <out.svg | out.png | out.tex | out.csv | out.json> [--set name=value]... [--width 800] [--height 600] [--dpi 96] [--view x0,y0,x1,y1] [--tex tikz|pgfplots] [--snippet] [--frames 60] [--fps 30] [--sweep name=from:to] [--sequence]";
+
+// コマンドラインで指定された書き出しの設定
+struct RenderArgs {
+    input: PathBuf,
+    output: PathBuf,
+    overrides: Vec<(String, String)>, // --set で指定されたUI要素の値
+    width: u32,
+    height: u32,
+    dpi: f64,
+    view: Option<[f64; 4]>,
+    tex_flavor: export::TexFlavor,
+    standalone: bool, // .tex をそのままコンパイルできる文書にする
+    frames: Option<usize>, // アニメーションのコマ数
+    fps: f64,
+    sweep: Option<(String, f64, f64)>, // 動かすスライダ (名前, 始めの値, 終わりの値)
+    sequence: bool,        // 連番PNGにする
+}
+
+fn parse_args(args: &[OsString]) -> Result<RenderArgs, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut overrides = Vec::new();
+    let mut width = 800;
+    let mut height = 600;
+    let mut dpi = export::BASE_DPI;
+    let mut view = None;
+    let mut tex_flavor = export::TexFlavor::Tikz;
+    let mut standalone = true;
+    let mut frames = None;
+    let mut fps = 30.0;
+    let mut sweep = None;
+    let mut sequence = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .map(|v| v.to_string_lossy().into_owned())
+                .ok_or_else(|| format!("{} に値を指定してください\n{}", name, USAGE))
+        };
+        match &*arg.to_string_lossy() {
+            "-o" | "--output" => output = Some(PathBuf::from(value("-o")?)),
+            "--set" => {
+                let text = value("--set")?;
+                let (name, v) = text.split_once('=')
+                    .ok_or_else(|| format!("--set は name=value の形で指定してください: {}", text))?;
+                overrides.push((name.trim().to_string(), v.trim().to_string()));
+            }
+            "--width" => width = value("--width")?.parse().map_err(|e| format!("--width: {}", e))?,
+            "--height" => height = value("--height")?.parse().map_err(|e| format!("--height: {}", e))?,
+            "--dpi" => dpi = value("--dpi")?.parse().map_err(|e| format!("--dpi: {}", e))?,
+            "--view" => {
+                let text = value("--view")?;
+                let n: Vec<f64> = text.split(',').filter_map(|x| x.trim().parse().ok()).collect();
+                match n[..] {
+                    [min_x, min_y, max_x, max_y] if min_x < max_x && min_y < max_y => view = Some([min_x, min_y, max_x, max_y]),
+                    _ => return Err(format!("--view は x0,y0,x1,y1 (x0 < x1, y0 < y1) の形で指定してください: {}", text)),
+                }
+            }
+            "--tex" => {
+                tex_flavor = match value("--tex")?.as_str() {
+                    "tikz" => export::TexFlavor::Tikz,
+                    "pgfplots" => export::TexFlavor::Pgfplots,
+                    other => return Err(format!("--tex は tikz か pgfplots を指定してください: {}", other)),
+                }
+            }
+            "--snippet" => standalone = false,
+            "--frames" => frames = Some(value("--frames")?.parse().map_err(|e| format!("--frames: {}", e))?),
+            "--fps" => fps = value("--fps")?.parse().map_err(|e| format!("--fps: {}", e))?,
+            "--sweep" => {
+                let text = value("--sweep")?;
+                let parsed = text.split_once('=').and_then(|(name, range)| {
+                    let (from, to) = range.split_once(':')?;
+                    Some((name.trim().to_string(), from.trim().parse().ok()?, to.trim().parse().ok()?))
+                });
+                sweep = Some(parsed.ok_or_else(|| format!("--sweep は name=from:to の形で指定してください: {}", text))?);
+            }
+            "--sequence" => sequence = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            other if other.starts_with('-') => return Err(format!("不明なオプションです: {}\n{}", other, USAGE)),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return Err(format!("入力ファイルは1つだけ指定してください\n{}", USAGE)),
+        }
+    }
+    if width == 0 || height == 0 {
+        return Err("--width と --height は1以上にしてください".to_string());
+    }
+    if !(dpi > 0.0 && dpi.is_finite()) {
+        return Err("--dpi は正の数にしてください".to_string());
+    }
+    if frames == Some(0) {
+        return Err("--frames は1以上にしてください".to_string());
+    }
+    if (sweep.is_some() || sequence) && frames.is_none() {
+        return Err("--sweep と --sequence は --frames と一緒に指定してください".to_string());
+    }
+    Ok(RenderArgs {
+        input: input.ok_or_else(|| USAGE.to_string())?,
+        output: output.ok_or_else(|| format!("出力ファイルを -o で指定してください\n{}", USAGE))?,
+        overrides,
+        width,
+        height,
+        dpi,
+        view,
+        tex_flavor,
+        standalone,
+        frames,
+        fps,
+        sweep,
+        sequence,
+    })
+}
+
+fn render(args: RenderArgs) -> Result<(), String> {
+    let mut app = ParametricPlotApp::headless();
+    let is_document = args.input.to_string_lossy().ends_with(".json");
+    if is_document {
+        app.open_document(&args.input)?;
+    } else {
+        app.open_script(&args.input)?;
+    }
+    let extension = args.output.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if let Some(frames) = args.frames {
+        return render_animation(app, &args, frames, &extension);
+    }
+
+    let mut figure = app.render_headless(&args.overrides)?;
+    if args.view.is_some() {
+        figure.view = args.view;
+    }
+    let contents = match extension.as_str() {
+        "svg" => export::to_svg(&figure, args.width, args.height).into_bytes(),
+        "png" => export::to_png(&figure, args.width, args.height, args.dpi)?,
+        "tex" => export::to_tex(&figure, args.width, args.height, args.tex_flavor, args.standalone).into_bytes(),
+        "csv" => export::to_csv(&figure).into_bytes(),
+        "json" => export::to_data_json(&figure).into_bytes(),
+        "gif" => return Err("GIFは --frames を指定してアニメーションとして書き出してください".to_string()),
+        _ => return Err(format!("対応していない出力形式です: {} (svg, png, tex, csv, json に対応しています)", args.output.display())),
+    };
+    std::fs::write(&args.output, contents).map_err(|e| format!("{}: {}", args.output.display(), e))
+}
+
+// 1コマずつ描画してアニメーションを書き出す
+fn render_animation(mut app: ParametricPlotApp, args: &RenderArgs, frames: usize, extension: &str) -> Result<(), String> {
+    let format = match (extension, args.sequence) {
+        ("gif", false) => export::AnimationFormat::Gif,
+        ("png", false) => export::AnimationFormat::Apng,
+        ("png", true) => export::AnimationFormat::PngSequence,
+        _ => return Err(format!("アニメーションは gif, png (--sequence は png) で書き出してください: {}", args.output.display())),
+    };
+    let source = match &args.sweep {
+        Some((name, from, to)) => RecordSource::Sweep { name: name.clone(), from: *from, to: *to },
+        None => RecordSource::Time,
+    };
+    app.prepare_headless(&args.overrides)?;
+    let figures = app.record_animation(&Recording { source, frames, fps: args.fps }, args.view)?;
+    export::write_animation(&figures, args.width, args.height, args.dpi, args.fps, format, &args.output)
+}
+
+// renderコマンドを実行し、終了コードを返す
+pub fn run(args: &[OsString]) -> i32 {
+    match parse_args(args).and_then(render) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<OsString> {
+        list.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn parses_input_output_and_overrides() {
+        let parsed = parse_args(&args(&["script.js", "-o", "out.png", "--set", "a = 2", "--set", "c=#ff0000", "--width", "400", "--dpi", "192"])).unwrap();
+        assert_eq!(parsed.input, PathBuf::from("script.js"));
+        assert_eq!(parsed.output, PathBuf::from("out.png"));
+        assert_eq!(parsed.overrides, vec![("a".to_string(), "2".to_string()), ("c".to_string(), "#ff0000".to_string())]);
+        assert_eq!((parsed.width, parsed.height, parsed.dpi), (400, 600, 192.0));
+        assert!(parsed.standalone);
+        assert_eq!(parsed.frames, None);
+    }
+
+    #[test]
+    fn parses_animation_options() {
+        let parsed = parse_args(&args(&["doc.graph.json", "-o", "out.gif", "--frames", "10", "--fps", "12", "--sweep", "a=0:1", "--view", "-1,-2,3,4"])).unwrap();
+        assert_eq!(parsed.frames, Some(10));
+        assert_eq!(parsed.fps, 12.0);
+        assert_eq!(parsed.sweep, Some(("a".to_string(), 0.0, 1.0)));
+        assert_eq!(parsed.view, Some([-1.0, -2.0, 3.0, 4.0]));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for list in [
+            &["s.js", "-o", "o.svg", "--set", "a"][..],
+            &["s.js", "-o", "o.svg", "--width", "wide"],
+            &["s.js", "-o", "o.svg", "--width", "0"],
+            &["s.js", "-o", "o.svg", "--dpi", "-1"],
+            &["s.js", "-o", "o.svg", "--view", "1,0,0,1"],
+            &["s.js", "-o", "o.svg", "--tex", "context"],
+            &["s.js", "-o", "o.gif", "--frames", "0"],
+            &["s.js", "-o", "o.gif", "--frames", "5", "--sweep", "a=0"],
+            &["s.js", "-o", "o.png", "--sequence"],
+            &["s.js", "-o"],
+            &["s.js"],
+            &["a.js", "b.js", "-o", "o.svg"],
+        ] {
+            assert!(parse_args(&args(list)).is_err(), "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let error = parse_args(&args(&["s.js", "-o", "o.svg", "--colour", "red"])).err().unwrap();
+        assert!(error.contains("--colour"), "{}", error);
+    }
+
+    #[test]
+    fn load_errors_exit_with_failure() {
+        let dir = std::env::temp_dir().join(format!("graphapp-render-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("broken.js");
+        std::fs::write(&script, "function setup() {").unwrap();
+        let output = dir.join("out.svg");
+        let list = [script.as_os_str(), "-o".as_ref(), output.as_os_str()].map(OsString::from);
+        assert_eq!(run(&list), 1);
+        assert!(!output.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,278 @@
+// 描画内容 (シーン)
+//
+// draw()の中で addParametricGraph・addVector・addPolygon・addText が追加した図形を、描画方法によらない形で持つ。
+// 画面上の表示 (plot.rs) や書き出し (export.rs) はこれを読み取って描く。
+
+use serde::{Deserialize, Serialize};
+
+// 色 ([r, g, b])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self([r, g, b])
+    }
+
+    pub fn r(self) -> u8 {
+        self.0[0]
+    }
+
+    pub fn g(self) -> u8 {
+        self.0[1]
+    }
+
+    pub fn b(self) -> u8 {
+        self.0[2]
+    }
+}
+
+// 曲線・ベクトルのスタイル
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineStyle {
+    pub color: Color,
+    pub weight: f32, // 線の太さ
+}
+
+// 多角形のスタイル
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PolygonStyle {
+    pub stroke: Color, // 枠線の色 (塗りつぶしは同じ色を薄くしたもの)
+    pub weight: f32,   // 枠線の太さ
+}
+
+// 文字のスタイル
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextStyle {
+    pub color: Color,
+    pub size: f32, // 文字の大きさ (ピクセル)
+}
+
+// シーンに含まれる図形
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SceneItem {
+    // 媒介変数表示の曲線 (paramsは各点を求めたときのtの値)
+    Curve {
+        name: String,
+        points: Vec<[f64; 2]>,
+        params: Vec<f64>,
+        style: LineStyle,
+    },
+    // 始点から終点への矢印 (tは始点・成分を求める関数に渡した値)
+    Vector {
+        name: String,
+        origin: [f64; 2],
+        tip: [f64; 2],
+        t: f64,
+        style: LineStyle,
+    },
+    Polygon {
+        name: String,
+        points: Vec<[f64; 2]>,
+        style: PolygonStyle,
+    },
+    // 位置を中心にして書く文字
+    Text {
+        text: String,
+        position: [f64; 2],
+        style: TextStyle,
+    },
+}
+
+impl SceneItem {
+    pub fn name(&self) -> &str {
+        match self {
+            SceneItem::Curve { name, .. } | SceneItem::Vector { name, .. } | SceneItem::Polygon { name, .. } => name,
+            SceneItem::Text { text, .. } => text,
+        }
+    }
+
+    // 図形に含まれる点 (ベクトルは始点と終点、文字は位置)
+    pub fn points(&self) -> Vec<[f64; 2]> {
+        match self {
+            SceneItem::Curve { points, .. } | SceneItem::Polygon { points, .. } => points.clone(),
+            SceneItem::Vector { origin, tip, .. } => vec![*origin, *tip],
+            SceneItem::Text { position, .. } => vec![*position],
+        }
+    }
+}
+
+// draw()1回分の描画内容 (追加された順に描く)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub items: Vec<SceneItem>,
+}
+
+impl Scene {
+    pub fn push(&mut self, item: SceneItem) {
+        self.items.push(item);
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // すべての図形の (有限な) 点を含む範囲 [x最小, y最小, x最大, y最大]
+    pub fn bounds(&self) -> Option<[f64; 4]> {
+        self.items.iter()
+            .flat_map(SceneItem::points)
+            .filter(|p| p[0].is_finite() && p[1].is_finite())
+            .fold(None, |bounds, p| {
+                Some(match bounds {
+                    None => [p[0], p[1], p[0], p[1]],
+                    Some([min_x, min_y, max_x, max_y]) => [min_x.min(p[0]), min_y.min(p[1]), max_x.max(p[0]), max_y.max(p[1])],
+                })
+            })
+    }
+}
+
+// ベクトルの矢じりの2つの端点を求める (ベクトルが非常に短い場合はNone)
+pub fn arrow_head(origin: [f64; 2], tip: [f64; 2]) -> Option<[[f64; 2]; 2]> {
+    const ARROW_HEAD_LENGTH: f64 = 0.15; // 矢じりの各辺の長さ
+    const ARROW_HEAD_ANGLE: f64 = std::f64::consts::PI / 7.0; // 矢じりの角度 (約25.7度)
+
+    // ベクトルの方向と長さを計算
+    let vec_dx = tip[0] - origin[0];
+    let vec_dy = tip[1] - origin[1];
+    let vec_len = (vec_dx.powi(2) + vec_dy.powi(2)).sqrt();
+    if vec_len < 1e-6 {
+        return None;
+    }
+
+    // 矢じりの長さを調整 (ベクトル本体が短い場合は矢じりも短くする)
+    let actual_arrow_head_length = ARROW_HEAD_LENGTH.min(vec_len * 0.4);
+
+    // 矢じりのための基準ベクトル（tipからoriginへ向かう方向）
+    let base_arrow_dx_norm = -vec_dx / vec_len;
+    let base_arrow_dy_norm = -vec_dy / vec_len;
+
+    // 基準ベクトルをangleだけ回転させた方向に矢じりの辺を伸ばす
+    let side = |angle: f64| {
+        let (sin_a, cos_a) = angle.sin_cos();
+        let dx = base_arrow_dx_norm * cos_a - base_arrow_dy_norm * sin_a;
+        let dy = base_arrow_dx_norm * sin_a + base_arrow_dy_norm * cos_a;
+        [tip[0] + actual_arrow_head_length * dx, tip[1] + actual_arrow_head_length * dy]
+    };
+    Some([side(ARROW_HEAD_ANGLE), side(-ARROW_HEAD_ANGLE)])
+}
+
+// ベクトルを描く線分 (本体 + 矢じり2線)
+pub fn vector_segments(origin: [f64; 2], tip: [f64; 2]) -> Vec<[[f64; 2]; 2]> {
+    let mut segments = vec![[origin, tip]];
+    if let Some([p1, p2]) = arrow_head(origin, tip) {
+        segments.push([tip, p1]);
+        segments.push([tip, p2]);
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: LineStyle = LineStyle { color: Color::rgb(255, 0, 0), weight: 1.5 };
+
+    fn curve(points: Vec<[f64; 2]>) -> SceneItem {
+        let params = (0..points.len()).map(|i| i as f64).collect();
+        SceneItem::Curve { name: "c".to_string(), points, params, style: STYLE }
+    }
+
+    fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+    }
+
+    #[test]
+    fn bounds_of_empty_scene_is_none() {
+        assert_eq!(Scene::default().bounds(), None);
+    }
+
+    #[test]
+    fn bounds_covers_all_items() {
+        let mut scene = Scene::default();
+        scene.push(curve(vec![[0.0, 1.0], [2.0, -1.0]]));
+        scene.push(SceneItem::Vector { name: "v".to_string(), origin: [-3.0, 0.0], tip: [1.0, 5.0], t: 0.0, style: STYLE });
+        scene.push(SceneItem::Polygon {
+            name: "p".to_string(),
+            points: vec![[4.0, 0.0], [0.0, -2.0], [1.0, 1.0]],
+            style: PolygonStyle { stroke: Color::rgb(0, 0, 0), weight: 1.0 },
+        });
+        assert_eq!(scene.bounds(), Some([-3.0, -2.0, 4.0, 5.0]));
+        scene.push(SceneItem::Text {
+            text: "label".to_string(),
+            position: [0.0, 6.0],
+            style: TextStyle { color: Color::rgb(0, 0, 0), size: 14.0 },
+        });
+        assert_eq!(scene.bounds(), Some([-3.0, -2.0, 4.0, 6.0]));
+    }
+
+    #[test]
+    fn bounds_skips_non_finite_points() {
+        let mut scene = Scene::default();
+        scene.push(curve(vec![[f64::NAN, 0.0], [1.0, 2.0], [f64::INFINITY, 100.0], [3.0, f64::NEG_INFINITY], [-1.0, 0.5]]));
+        assert_eq!(scene.bounds(), Some([-1.0, 0.5, 1.0, 2.0]));
+
+        let mut scene = Scene::default();
+        scene.push(curve(vec![[f64::NAN, f64::NAN]]));
+        assert_eq!(scene.bounds(), None);
+    }
+
+    #[test]
+    fn arrow_head_is_symmetric_behind_tip() {
+        let [p1, p2] = arrow_head([0.0, 0.0], [2.0, 0.0]).unwrap();
+        // 矢じりは終点から0.15の長さで、x軸に対して対称に後ろ向きに開く
+        assert!((distance(p1, [2.0, 0.0]) - 0.15).abs() < 1e-12);
+        assert!((distance(p2, [2.0, 0.0]) - 0.15).abs() < 1e-12);
+        assert!(p1[0] < 2.0 && p2[0] < 2.0);
+        assert!((p1[0] - p2[0]).abs() < 1e-12);
+        assert!((p1[1] + p2[1]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn arrow_head_shrinks_for_short_vectors() {
+        let [p1, _] = arrow_head([0.0, 0.0], [0.0, 0.1]).unwrap();
+        assert!((distance(p1, [0.0, 0.1]) - 0.04).abs() < 1e-12);
+    }
+
+    #[test]
+    fn degenerate_vector_has_no_arrow_head() {
+        assert_eq!(arrow_head([1.0, 1.0], [1.0, 1.0]), None);
+        assert_eq!(arrow_head([0.0, 0.0], [1e-7, 0.0]), None);
+        assert_eq!(vector_segments([1.0, 1.0], [1.0, 1.0]), vec![[[1.0, 1.0], [1.0, 1.0]]]);
+    }
+
+    #[test]
+    fn vector_segments_are_body_and_two_head_lines() {
+        let segments = vector_segments([0.0, 0.0], [0.0, 1.0]);
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0], [[0.0, 0.0], [0.0, 1.0]]);
+        let [p1, p2] = arrow_head([0.0, 0.0], [0.0, 1.0]).unwrap();
+        assert_eq!(segments[1], [[0.0, 1.0], p1]);
+        assert_eq!(segments[2], [[0.0, 1.0], p2]);
+    }
+
+    #[test]
+    fn scene_items_round_trip_through_json() {
+        let scene = Scene {
+            items: vec![
+                curve(vec![[0.0, 0.0], [0.5, 0.25]]),
+                SceneItem::Vector { name: "v".to_string(), origin: [1.0, 2.0], tip: [3.0, 4.0], t: 0.5, style: STYLE },
+                SceneItem::Polygon {
+                    name: "p".to_string(),
+                    points: vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+                    style: PolygonStyle { stroke: Color::rgb(1, 2, 3), weight: 2.0 },
+                },
+            ],
+        };
+        let json = serde_json::to_value(&scene).unwrap();
+        assert_eq!(json["items"][0]["type"], "curve");
+        assert_eq!(json["items"][1]["type"], "vector");
+        assert_eq!(json["items"][2]["type"], "polygon");
+        let restored: Scene = serde_json::from_value(json).unwrap();
+        assert_eq!(restored, scene);
+    }
+}
@@ -0,0 +1,437 @@
+// スクリプトの実行環境
+//
+// アプリはScriptEngineを通してコードの読み込み・setup()/draw()の呼び出し・関数の登録・グローバル変数の設定を行う。
+// 値はNativeValue (native.rs) でやり取りするため、描画APIは実行環境によらず同じ実装で動く。
+// 既定の実装はBoa (JavaScript) を使うBoaEngine。
+
+use crate::native::NativeValue;
+use boa_engine::gc::{custom_trace, Finalize, GcRefCell, Trace};
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{js_string, Context as BoaContext, JsData, JsNativeError, JsObject, JsResult, JsString, JsValue, NativeFunction, Source};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// スクリプトで定義された関数 (中身は実行環境ごとに異なる)
+#[derive(Clone)]
+pub struct ScriptFunction(Rc<dyn Any>);
+
+impl ScriptFunction {
+    pub fn new(handle: impl Any) -> Self {
+        Self(Rc::new(handle))
+    }
+
+    // 実行環境が自分の関数を取り出す
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+impl fmt::Debug for ScriptFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ScriptFunction")
+    }
+}
+
+impl PartialEq for ScriptFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// スクリプトの関数を呼び出す (登録した関数の中からも使える)
+pub trait ScriptCaller {
+    fn call_function(&mut self, function: &ScriptFunction, args: &[NativeValue]) -> Result<NativeValue, String>;
+}
+
+// スクリプトから呼び出せるアプリ側の関数
+pub type HostFn = Rc<dyn Fn(&mut dyn ScriptCaller, &[NativeValue]) -> Result<NativeValue, String>>;
+
+pub trait ScriptEngine: ScriptCaller {
+    // 実行環境を作り直す (前に読み込んだコードのグローバル変数や関数、登録した関数を消す)
+    fn reset(&mut self);
+
+    // コードを読み込んで実行する
+    fn load(&mut self, source: &str) -> Result<(), String>;
+
+    // 関数をグローバルに登録する
+    fn register_function(&mut self, name: &str, function: HostFn) -> Result<(), String>;
+
+    // 関数をまとめたオブジェクトをグローバルに登録する (スクリプトからは name.function(...) で呼び出す)
+    fn register_module(&mut self, name: &str, functions: &[(String, HostFn)]) -> Result<(), String>;
+
+    fn set_global(&mut self, name: &str, value: NativeValue) -> Result<(), String>;
+
+    // グローバル変数の値 (定義されていなければUndefined)
+    fn global(&mut self, name: &str) -> NativeValue;
+
+    fn has_function(&mut self, name: &str) -> bool {
+        matches!(self.global(name), NativeValue::Function(_))
+    }
+
+    // グローバル関数を呼び出す (setup・draw・onClick など)
+    fn call_global(&mut self, name: &str, args: &[NativeValue]) -> Result<NativeValue, String> {
+        match self.global(name) {
+            NativeValue::Function(function) => self.call_function(&function, args),
+            _ => Err(format!("{} is not defined", name)),
+        }
+    }
+}
+
+// Boaを使ったJavaScriptの実行環境
+pub struct BoaEngine {
+    context: BoaContext,
+    // グローバル変数がlet/constで宣言されているか (名前ごとに一度だけ調べ、コードを読み込むたびに調べ直す)
+    lexical_globals: HashMap<String, bool>,
+}
+
+// 入れ子の配列・オブジェクトを変換する深さの上限 (循環参照で止まらなくなるのを防ぐ)
+const MAX_CONVERT_DEPTH: usize = 64;
+
+impl BoaEngine {
+    pub fn new() -> Self {
+        let mut engine = Self { context: BoaContext::default(), lexical_globals: HashMap::new() };
+        engine.reset();
+        engine
+    }
+}
+
+impl Default for BoaEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptCaller for BoaEngine {
+    fn call_function(&mut self, function: &ScriptFunction, args: &[NativeValue]) -> Result<NativeValue, String> {
+        call_function(&mut self.context, function, args)
+    }
+}
+
+impl ScriptEngine for BoaEngine {
+    fn reset(&mut self) {
+        self.context = BoaContext::default();
+        self.context.insert_data(FunctionTable::default());
+        self.lexical_globals.clear();
+
+        // console.log/console.errorをstdout/stderr経由でJSON出力するように定義
+        // (stdout・stderrはアプリ側で登録する)
+        let console_js = r#"
+            try {
+                if (typeof globalThis.console !== 'object' || globalThis.console === null) {
+                    globalThis.console = {};
+                }
+                globalThis.console.log = function(...args) {
+                    try { stdout(args.map(x=>JSON.stringify(x)).join(" ")); } catch(e) {}
+                };
+                globalThis.console.error = function(...args) {
+                    try { stderr(args.map(x=>JSON.stringify(x)).join(" ")); } catch(e) {}
+                };
+            } catch(e) { stderr('[console patch error] ' + e); }
+        "#;
+        if let Err(e) = self.context.eval(Source::from_bytes(console_js)) {
+            log::error!("Error setting up console: {:?}", e);
+        }
+    }
+
+    fn load(&mut self, source: &str) -> Result<(), String> {
+        self.lexical_globals.clear();
+        self.context.eval(Source::from_bytes(source)).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn register_function(&mut self, name: &str, function: HostFn) -> Result<(), String> {
+        self.context
+            .register_global_builtin_callable(JsString::from(name), 0, to_native_function(function))
+            .map_err(|e| e.to_string())
+    }
+
+    fn register_module(&mut self, name: &str, functions: &[(String, HostFn)]) -> Result<(), String> {
+        let mut object = ObjectInitializer::new(&mut self.context);
+        for (function_name, function) in functions {
+            object.function(to_native_function(function.clone()), JsString::from(function_name.as_str()), 0);
+        }
+        let object = object.build();
+        self.context
+            .register_global_property(JsString::from(name), object, Attribute::all())
+            .map_err(|e| e.to_string())
+    }
+
+    fn set_global(&mut self, name: &str, value: NativeValue) -> Result<(), String> {
+        let value = into_js(value, &mut self.context);
+        self.context
+            .register_global_property(JsString::from(name), value, Attribute::all())
+            .map_err(|e| e.to_string())
+    }
+
+    // 毎フレーム呼ばれる (draw・onHoverなど) ため、通常はグローバルオブジェクトを直接読み、evalはlet/constの変数を読むときだけ使う
+    fn global(&mut self, name: &str) -> NativeValue {
+        let global = self.context.global_object();
+        let mut value = global.get(JsString::from(name), &mut self.context).unwrap_or(JsValue::undefined());
+        // let/constで宣言された場合はグローバルオブジェクトに載らないため、evalで読む
+        if value.is_undefined() && self.is_lexical_global(name) {
+            value = self.context.eval(Source::from_bytes(name)).unwrap_or(JsValue::undefined());
+        }
+        from_js(&value, &mut self.context, 0).unwrap_or(NativeValue::Undefined)
+    }
+}
+
+impl BoaEngine {
+    // nameがグローバルオブジェクトにない (let/constなどで宣言された) 変数か
+    // 宣言されているかどうかはコードを読み込んだ後には変わらないため、結果を覚えておく
+    fn is_lexical_global(&mut self, name: &str) -> bool {
+        if !is_identifier(name) {
+            return false;
+        }
+        if let Some(&lexical) = self.lexical_globals.get(name) {
+            return lexical;
+        }
+        // 宣言されていなければReferenceErrorになる
+        let source = format!("try {{ {}; true }} catch (e) {{ false }}", name);
+        let lexical = self.context.eval(Source::from_bytes(source.as_str())).is_ok_and(|value| value.to_boolean());
+        self.lexical_globals.insert(name.to_string(), lexical);
+        lexical
+    }
+}
+
+// evalで読んでも安全な名前か
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+// スクリプトの関数を保持する表
+// 関数を受け取ったアプリ側の値 (ボタンのコールバックなど) は登録した関数のクロージャからも参照されるため、
+// ScriptFunctionにはJSの値を持たせず、この表の番号だけを持たせる
+#[derive(Default)]
+struct FunctionTable {
+    slots: GcRefCell<Vec<Option<JsObject>>>,
+    released: Rc<RefCell<Vec<usize>>>, // 参照されなくなった番号 (BoaFunctionの破棄時に追加される)
+}
+
+impl Finalize for FunctionTable {}
+
+// SAFETY: Traceは、GCで管理される値をすべてmarkすれば正しい。
+// FunctionTableのうちGCで管理される値 (JsObject) を持つのはslotsだけで、custom_trace!でslotsをmarkしている。
+// releasedは番号 (usize) だけを持ち、JSの値を含まない。
+// FunctionTableはContextのデータ (JsData) として登録するため、Contextが生きている間はGCからtraceされ、slotsの関数は回収されない
+unsafe impl Trace for FunctionTable {
+    custom_trace!(this, mark, mark(&this.slots));
+}
+
+impl JsData for FunctionTable {}
+
+// ScriptFunctionの中身 (FunctionTableの番号)
+struct BoaFunction {
+    slot: usize,
+    released: Rc<RefCell<Vec<usize>>>, // どの表の番号かの区別にも使う
+}
+
+impl Drop for BoaFunction {
+    fn drop(&mut self) {
+        self.released.borrow_mut().push(self.slot);
+    }
+}
+
+fn store_function(context: &BoaContext, function: JsObject) -> ScriptFunction {
+    let table = context.get_data::<FunctionTable>().expect("FunctionTable is not initialized");
+    let released: Vec<usize> = table.released.borrow_mut().drain(..).collect();
+    let mut slots = table.slots.borrow_mut();
+    for slot in released {
+        slots[slot] = None;
+    }
+    let slot = match slots.iter().position(Option::is_none) {
+        Some(slot) => slot,
+        None => {
+            slots.push(None);
+            slots.len() - 1
+        }
+    };
+    slots[slot] = Some(function);
+    ScriptFunction::new(BoaFunction { slot, released: table.released.clone() })
+}
+
+fn load_function(context: &BoaContext, function: &ScriptFunction) -> Result<JsObject, String> {
+    let table = context.get_data::<FunctionTable>().expect("FunctionTable is not initialized");
+    function.downcast_ref::<BoaFunction>()
+        .filter(|function| Rc::ptr_eq(&function.released, &table.released))
+        .and_then(|function| table.slots.borrow().get(function.slot).cloned().flatten())
+        .ok_or_else(|| "別の実行環境で作られた関数です".to_string())
+}
+
+fn call_function(context: &mut BoaContext, function: &ScriptFunction, args: &[NativeValue]) -> Result<NativeValue, String> {
+    let function = load_function(context, function)?;
+    let args: Vec<JsValue> = args.iter().map(|arg| into_js(arg.clone(), context)).collect();
+    let result = function.call(&JsValue::undefined(), &args, context).map_err(|e| e.to_string())?;
+    from_js(&result, context, 0).map_err(|e| e.to_string())
+}
+
+// 登録した関数の中からスクリプトの関数を呼び出すための窓口
+struct BoaCaller<'a> {
+    context: &'a mut BoaContext,
+}
+
+impl ScriptCaller for BoaCaller<'_> {
+    fn call_function(&mut self, function: &ScriptFunction, args: &[NativeValue]) -> Result<NativeValue, String> {
+        call_function(self.context, function, args)
+    }
+}
+
+// 引数と戻り値を変換してアプリ側の関数を呼び出すJSの関数を作る
+fn to_native_function(function: HostFn) -> NativeFunction {
+    let call = move |_this: &JsValue, args: &[JsValue], context: &mut BoaContext| -> JsResult<JsValue> {
+        let args = args.iter().map(|arg| from_js(arg, context, 0)).collect::<JsResult<Vec<_>>>()?;
+        let mut caller = BoaCaller { context };
+        let result = function(&mut caller, &args).map_err(|message| JsNativeError::error().with_message(message))?;
+        Ok(into_js(result, caller.context))
+    };
+    // SAFETY: from_closureは、クロージャがGCで管理される値 (JsValue・JsObjectなど) を捕捉しない場合に安全 (捕捉した値はtraceされず、回収された後に使われうる)。
+    // このクロージャが捕捉するのはHostFn (Rcで持つRustの関数) だけで、HostFnが受け取り・返す値はNativeValueなのでJSの値を含まない。
+    // アプリ側がスクリプトの関数を持つ場合もScriptFunction (BoaFunction) にはFunctionTableの番号だけを持たせ、
+    // JSの関数そのものはtraceされるFunctionTableに置いている
+    unsafe { NativeFunction::from_closure(call) }
+}
+
+fn from_js(value: &JsValue, context: &mut BoaContext, depth: usize) -> JsResult<NativeValue> {
+    if value.is_null_or_undefined() {
+        return Ok(NativeValue::Undefined);
+    }
+    if let Some(b) = value.as_boolean() {
+        return Ok(NativeValue::Bool(b));
+    }
+    if let Some(n) = value.as_number() {
+        return Ok(NativeValue::Number(n));
+    }
+    if let Some(s) = value.as_string() {
+        return Ok(NativeValue::String(s.to_std_string_escaped()));
+    }
+    if let Some(function) = value.as_callable() {
+        return Ok(NativeValue::Function(store_function(context, function.clone())));
+    }
+    let Some(obj) = value.as_object() else {
+        return Err(JsNativeError::typ()
+            .with_message(format!("変換できない値です: {}", value.display()))
+            .into());
+    };
+    if depth >= MAX_CONVERT_DEPTH {
+        return Err(JsNativeError::range().with_message("配列・オブジェクトの入れ子が深すぎます").into());
+    }
+    if obj.is_array() {
+        let length = obj.get(js_string!("length"), context)?.to_number(context)? as u64;
+        let mut items = Vec::with_capacity(length as usize);
+        for i in 0..length {
+            items.push(from_js(&obj.get(i, context)?, context, depth + 1)?);
+        }
+        return Ok(NativeValue::Array(items));
+    }
+    let mut fields = std::collections::BTreeMap::new();
+    for key in object_keys(obj, context)? {
+        let field = obj.get(JsString::from(key.as_str()), context)?;
+        fields.insert(key, from_js(&field, context, depth + 1)?);
+    }
+    Ok(NativeValue::Object(fields))
+}
+
+// Object.keys(obj) と同じ (列挙可能な自身のプロパティ名)
+fn object_keys(obj: &JsObject, context: &mut BoaContext) -> JsResult<Vec<String>> {
+    let object_ctor = context.global_object().get(js_string!("Object"), context)?;
+    let keys_fn = object_ctor.as_object()
+        .map(|ctor| ctor.get(js_string!("keys"), context))
+        .transpose()?
+        .and_then(|keys| keys.as_callable().cloned())
+        .ok_or_else(|| JsNativeError::typ().with_message("Object.keys is not available"))?;
+    let keys = keys_fn.call(&object_ctor, &[JsValue::from(obj.clone())], context)?;
+    let NativeValue::Array(keys) = from_js(&keys, context, 0)? else {
+        return Ok(Vec::new());
+    };
+    Ok(keys.into_iter().filter_map(|key| key.as_str().map(str::to_string)).collect())
+}
+
+fn into_js(value: NativeValue, context: &mut BoaContext) -> JsValue {
+    match value {
+        NativeValue::Undefined => JsValue::undefined(),
+        NativeValue::Bool(b) => JsValue::from(b),
+        NativeValue::Number(n) => JsValue::from(n),
+        NativeValue::String(s) => JsValue::from(JsString::from(s.as_str())),
+        NativeValue::Array(items) => {
+            let items: Vec<JsValue> = items.into_iter().map(|item| into_js(item, context)).collect();
+            JsArray::from_iter(items, context).into()
+        }
+        NativeValue::Object(fields) => {
+            let fields: Vec<(JsString, JsValue)> = fields.into_iter()
+                .map(|(key, field)| (JsString::from(key.as_str()), into_js(field, context)))
+                .collect();
+            let mut object = ObjectInitializer::new(context);
+            for (key, field) in fields {
+                object.property(key, field, Attribute::all());
+            }
+            object.build().into()
+        }
+        NativeValue::Function(function) => load_function(context, &function)
+            .map(JsValue::from)
+            .unwrap_or(JsValue::undefined()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_fn(f: impl Fn(&mut dyn ScriptCaller, &[NativeValue]) -> Result<NativeValue, String> + 'static) -> HostFn {
+        Rc::new(f)
+    }
+
+    #[test]
+    fn scripts_call_registered_closures() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        let mut engine = BoaEngine::new();
+        engine.register_function("double", host_fn(move |_, args| {
+            let x = args.first().and_then(NativeValue::as_f64).ok_or("number expected")?;
+            recorded.borrow_mut().push(x);
+            Ok(NativeValue::Number(x * 2.0))
+        })).unwrap();
+        engine.load("function f(x) { return double(x) + 1; }").unwrap();
+        assert_eq!(engine.call_global("f", &[NativeValue::Number(3.0)]), Ok(NativeValue::Number(7.0)));
+        assert_eq!(*calls.borrow(), [3.0]);
+        // 登録した関数のエラーはスクリプトの例外になる
+        assert!(engine.call_global("f", &[NativeValue::String("a".to_string())]).is_err());
+    }
+
+    #[test]
+    fn registered_closures_call_back_into_the_script() {
+        let mut engine = BoaEngine::new();
+        engine.register_function("applyTwice", host_fn(|caller, args| {
+            let f = args.first().and_then(NativeValue::as_function).ok_or("function expected")?;
+            let once = caller.call_function(f, &args[1..2])?;
+            caller.call_function(f, &[once])
+        })).unwrap();
+        engine.load("function g(x) { return applyTwice(y => y * 3, x); }").unwrap();
+        assert_eq!(engine.call_global("g", &[NativeValue::Number(2.0)]), Ok(NativeValue::Number(18.0)));
+    }
+
+    #[test]
+    fn reads_lexical_globals_and_forgets_them_after_reload() {
+        let mut engine = BoaEngine::new();
+        engine.load("function draw() {} let onHover; const count = 1; var total = 2;").unwrap();
+        assert!(engine.has_function("draw"));
+        assert!(!engine.has_function("onHover"));
+        assert!(!engine.has_function("onClick"));
+        assert_eq!(engine.global("count"), NativeValue::Number(1.0));
+        assert_eq!(engine.global("total"), NativeValue::Number(2.0));
+        // 宣言済みの変数に後から関数を代入した場合も読める
+        engine.load("onHover = function() {};").unwrap();
+        assert!(engine.has_function("onHover"));
+
+        engine.reset();
+        engine.load("const onClick = () => 1;").unwrap();
+        assert!(!engine.has_function("draw"));
+        assert!(engine.has_function("onClick"));
+        assert_eq!(engine.call_global("onClick", &[]), Ok(NativeValue::Number(1.0)));
+        assert_eq!(engine.global("count"), NativeValue::Undefined);
+    }
+}
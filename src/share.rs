@@ -0,0 +1,141 @@
+// 共有リンクの符号化
+//
+// 共有する状態をJSONにしてdeflateで圧縮し、URLで使えるbase64 (パディングなし) にしたものに
+// 形式のバージョンを表す接頭辞 "v1." を付ける。
+// base64の文字に '.' は含まれないため、接頭辞のない古い形式 (コードをそのままbase64にしたもの) と区別できる。
+
+use base64::{engine::general_purpose, Engine as _};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+
+// 共有リンクの基準となるWeb版のURL
+#[cfg(not(target_arch = "wasm32"))]
+pub const SHARE_BASE_URL: &str = "https://bem130.github.io/graphapp/";
+
+// 現在の形式のバージョン
+const FORMAT_VERSION: u32 = 1;
+
+// 展開後のサイズの上限 (壊れたリンクや悪意のあるリンクでメモリを使い果たさないようにする)
+const MAX_DECODED_BYTES: u64 = 16 * 1024 * 1024;
+
+// 状態を共有リンク用の文字列にする
+pub fn encode<T: Serialize>(state: &T) -> String {
+    let json = serde_json::to_vec(state).expect("共有する状態はJSONに変換できる");
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&json).expect("メモリへの書き込みは失敗しない");
+    let compressed = encoder.finish().expect("メモリへの書き込みは失敗しない");
+    format!("v{}.{}", FORMAT_VERSION, general_purpose::URL_SAFE_NO_PAD.encode(compressed))
+}
+
+// encodeで作った文字列から状態を復元する
+pub fn decode<T: DeserializeOwned>(token: &str) -> Result<T, String> {
+    let Some((version, payload)) = token.strip_prefix('v').and_then(|rest| rest.split_once('.')) else {
+        return Err("共有リンクの形式が正しくありません".to_string());
+    };
+    let version: u32 = version.parse().map_err(|_| "共有リンクの形式が正しくありません".to_string())?;
+    if version != FORMAT_VERSION {
+        return Err(format!("対応していない共有リンクの形式です (v{})", version));
+    }
+    let compressed = general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|e| format!("共有リンクを読み込めません: {}", e))?;
+    let mut json = Vec::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .take(MAX_DECODED_BYTES + 1)
+        .read_to_end(&mut json)
+        .map_err(|e| format!("共有リンクを展開できません: {}", e))?;
+    if json.len() as u64 > MAX_DECODED_BYTES {
+        return Err("共有リンクの内容が大きすぎます".to_string());
+    }
+    serde_json::from_slice(&json).map_err(|e| format!("共有リンクの内容が正しくありません: {}", e))
+}
+
+// 古い形式 (?code= にコードをそのままbase64で入れたもの) の値を読み込む
+pub fn decode_legacy(value: &str) -> Option<String> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(value)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct State {
+        code: String,
+        values: Vec<f64>,
+    }
+
+    fn state() -> State {
+        State { code: "function draw() { addParametricGraph(\"円\", t => [Math.cos(t), Math.sin(t)]); }".to_string(), values: vec![0.5, -1.0] }
+    }
+
+    // 任意のバイト列をdeflateで圧縮して "v1." 形式のトークンにする
+    fn token_of(bytes: &[u8]) -> String {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        format!("v1.{}", general_purpose::URL_SAFE_NO_PAD.encode(encoder.finish().unwrap()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let token = encode(&state());
+        assert!(token.starts_with("v1."));
+        assert!(token.bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_' | b'.')));
+        assert_eq!(decode::<State>(&token), Ok(state()));
+    }
+
+    #[test]
+    fn legacy_code_links() {
+        let code = "addSlider(\"a\", {min: 0, max: 1}); // 日本語のコメント ~?>";
+        let value = general_purpose::URL_SAFE_NO_PAD.encode(code);
+        assert_eq!(decode_legacy(&value).as_deref(), Some(code));
+        // 古い形式の値は新しい形式としては読めない
+        assert!(decode::<State>(&value).is_err());
+    }
+
+    #[test]
+    fn legacy_rejects_invalid_values() {
+        assert_eq!(decode_legacy("not base64!"), None);
+        assert_eq!(decode_legacy(&general_purpose::URL_SAFE_NO_PAD.encode([0xff, 0xfe, 0xfd])), None); // UTF-8でない
+    }
+
+    #[test]
+    fn unknown_version_prefixes() {
+        let payload = encode(&state()).split_once('.').unwrap().1.to_string();
+        assert_eq!(decode::<State>(&format!("v2.{}", payload)), Err("対応していない共有リンクの形式です (v2)".to_string()));
+        for token in [format!("v0.{}", payload), format!("v99.{}", payload)] {
+            assert!(decode::<State>(&token).unwrap_err().starts_with("対応していない"));
+        }
+        for token in [format!("vx.{}", payload), format!("x1.{}", payload), format!("v1{}", payload), String::new()] {
+            assert_eq!(decode::<State>(&token), Err("共有リンクの形式が正しくありません".to_string()));
+        }
+    }
+
+    #[test]
+    fn corrupt_input() {
+        let token = encode(&state());
+        // base64として読めない
+        assert!(decode::<State>(&format!("{}!!", token)).unwrap_err().starts_with("共有リンクを読み込めません"));
+        // deflateとして展開できない
+        let garbage = format!("v1.{}", general_purpose::URL_SAFE_NO_PAD.encode([0xff; 16]));
+        assert!(decode::<State>(&garbage).unwrap_err().starts_with("共有リンクを展開できません"));
+        // 途中で切れている
+        let truncated = &token[..token.len() / 2];
+        assert!(decode::<State>(truncated).is_err());
+        // 展開できるがJSONとして正しくない
+        assert!(decode::<State>(&token_of(b"{\"code\": 1}")).unwrap_err().starts_with("共有リンクの内容が正しくありません"));
+    }
+
+    #[test]
+    fn decoded_size_is_limited() {
+        let at_limit = format!("\"{}\"", "a".repeat(MAX_DECODED_BYTES as usize - 2));
+        assert_eq!(decode::<String>(&token_of(at_limit.as_bytes())).map(|s| s.len()), Ok(MAX_DECODED_BYTES as usize - 2));
+        let over_limit = format!("\"{}\"", "a".repeat(MAX_DECODED_BYTES as usize - 1));
+        assert_eq!(decode::<String>(&token_of(over_limit.as_bytes())), Err("共有リンクの内容が大きすぎます".to_string()));
+    }
+}